@@ -0,0 +1,176 @@
+//! The agent module lets players be controlled by any kind of bot.
+//! A bot is given the player's view of the world and returns its orders for the turn.
+//! Besides the built-in AI, bots can run as external programs speaking JSON lines.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::process::Child;
+use std::process::ChildStdin;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ai::AI;
+use crate::Cube;
+use crate::World;
+
+// Seconds an external bot may take to answer, before its turn is forfeited and it is restarted.
+const DEFAULT_BOT_TIMEOUT: f64 = 5.;
+
+/// Moves the army at origin to target, attacking or capturing as needed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Order {
+    pub origin: Cube<i32>,
+    pub target: Cube<i32>,
+}
+
+pub trait Agent {
+    /// Returns the orders for this turn, best first. Orders beyond the available actions are ignored.
+    fn plan_orders(&mut self, player_index: usize, view: &World, actions: i32) -> Vec<Order>;
+}
+
+impl Agent for AI {
+    fn plan_orders(&mut self, player_index: usize, view: &World, actions: i32) -> Vec<Order> {
+        self.plan_turn(&player_index, view, actions).into_iter().map(|scored_move| {
+            Order{origin: scored_move.origin, target: scored_move.target}
+        }).collect()
+    }
+}
+
+/// The bot controlling a player. Saved untagged, so that saves with a bare AI still load.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Bot {
+    Scorer(AI),
+    External(ExternalBot),
+}
+
+impl From<AI> for Bot {
+    fn from(ai: AI) -> Self {
+        Bot::Scorer(ai)
+    }
+}
+
+impl Agent for Bot {
+    fn plan_orders(&mut self, player_index: usize, view: &World, actions: i32) -> Vec<Order> {
+        match self {
+            Bot::Scorer(ai) => ai.plan_orders(player_index, view, actions),
+            Bot::External(bot) => bot.plan_orders(player_index, view, actions),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TurnRequest<'a> {
+    player: usize,
+    actions: i32,
+    world: &'a World,
+}
+
+#[derive(Deserialize)]
+struct TurnResponse {
+    orders: Vec<Order>,
+}
+
+struct BotProcess {
+    child: Child,
+    stdin: ChildStdin,
+    // Lines read from stdout by a thread of their own, so that waiting for them can time out.
+    lines: mpsc::Receiver<std::io::Result<String>>,
+}
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// A bot running as a subprocess. Every turn it is sent one JSON line on stdin:
+/// `{"player": 0, "actions": 5, "world": {"q_r": tile, ...}}`,
+/// and must answer with one JSON line on stdout: `{"orders": [{"origin": "q_r", "target": "q_r"}, ...]}`.
+/// The process is started on its first turn and restarted if it fails or takes longer than `timeout` seconds.
+#[derive(Serialize, Deserialize)]
+pub struct ExternalBot {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_timeout")]
+    pub timeout: f64,
+    #[serde(skip)]
+    process: Option<BotProcess>,
+}
+
+impl Clone for ExternalBot {
+    fn clone(&self) -> Self {
+        ExternalBot{timeout: self.timeout, ..ExternalBot::new(&self.command, self.args.clone())}
+    }
+}
+
+fn default_timeout() -> f64 {
+    DEFAULT_BOT_TIMEOUT
+}
+
+impl ExternalBot {
+    pub fn new(command: &str, args: Vec<String>) -> Self {
+        ExternalBot{command: command.to_string(), args, timeout: DEFAULT_BOT_TIMEOUT, process: None}
+    }
+
+    fn spawn(&self) -> std::io::Result<BotProcess> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        // Ends once the process is killed and its stdout closes
+        std::thread::spawn(move || loop {
+            let mut line = String::new();
+            let result = stdout.read_line(&mut line).map(|_| line);
+            let is_done = !matches!(&result, Ok(line) if !line.is_empty());
+            if sender.send(result).is_err() || is_done {break}
+        });
+        Ok(BotProcess{child, stdin, lines})
+    }
+
+    fn request(&mut self, request: &TurnRequest) -> std::io::Result<Vec<Order>> {
+        if self.process.is_none() {
+            self.process = Some(self.spawn()?);
+        }
+        let process = self.process.as_mut().unwrap();
+        serde_json::to_writer(&mut process.stdin, request)?;
+        process.stdin.write_all(b"\n")?;
+        process.stdin.flush()?;
+
+        let line = match process.lines.recv_timeout(Duration::from_secs_f64(self.timeout.max(0.))) {
+            Ok(line) => line?,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the bot took too long")),
+            Err(mpsc::RecvTimeoutError::Disconnected) => String::new(),
+        };
+        if line.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the bot exited"))
+        }
+        let response: TurnResponse = serde_json::from_str(&line)?;
+        Ok(response.orders)
+    }
+}
+
+impl Agent for ExternalBot {
+    fn plan_orders(&mut self, player_index: usize, view: &World, actions: i32) -> Vec<Order> {
+        let request = TurnRequest{player: player_index, actions, world: view};
+        match self.request(&request) {
+            Ok(orders) => orders,
+            Err(e) => {
+                eprintln!("External bot {} failed: {}", self.command, e);
+                self.process = None;
+                vec!()
+            }
+        }
+    }
+}
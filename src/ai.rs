@@ -1,16 +1,29 @@
 /// The AI module
 
 use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use macroquad::miniquad::date;
 
 use serde::Deserialize;
 use serde::Serialize;
+use strum::EnumIter;
+use rand::Rng;
+
+use crate::agent::Order;
+use crate::rng;
 
 use crate::Cube;
 use crate::Tile;
 use crate::World;
 use crate::TileCategory;
 use crate::LocalityCategory;
+use crate::world::MAX_STACK_SIZE;
 
 // enum SCORES {
 //     Farmland(i32),
@@ -20,7 +33,7 @@ use crate::LocalityCategory;
 //     Manpower(i32),
 // }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Scores {
     manpower: i32,
     water: i32,
@@ -32,9 +45,156 @@ pub struct Scores {
     satellite_capital: i32,
 }
 
-#[derive(Debug)]
+impl Scores {
+    pub const COUNT: usize = 8;
+
+    /// The weights as a flat array, in declaration order, for the tuner to work on.
+    pub fn to_weights(&self) -> [i32; Scores::COUNT] {
+        [self.manpower, self.water, self.farmland, self.city, self.port_city, self.airport, self.capital, self.satellite_capital]
+    }
+    pub fn from_weights(weights: [i32; Scores::COUNT]) -> Self {
+        let [manpower, water, farmland, city, port_city, airport, capital, satellite_capital] = weights;
+        Scores{manpower, water, farmland, city, port_city, airport, capital, satellite_capital}
+    }
+    pub fn to_json(&self, path: &str) {
+        let file = File::create(&path).expect("Failed to open the file.");
+
+        match serde_json::to_writer_pretty(file, self) {
+            Ok(()) => println!("Scores saved to {}", path),
+            Err(e) => eprintln!("Error during serialization: {}", e),
+        }
+    }
+    pub fn from_json(path: &str) -> Self {
+        let f = File::open(path)
+            .expect("file should open read only");
+
+        serde_json::from_reader(f).expect("file should be proper JSON")
+    }
+}
+
+/// The combat strength of the strongest enemy army able to reach every cube on its next turn.
+/// Armies of players the AI is at peace with are not a threat.
+pub struct ThreatMap {
+    threats: HashMap<Cube<i32>, i32>,
+}
+
+impl ThreatMap {
+    pub fn new(own_player_index: &usize, world: &World) -> Self {
+        let mut threats = HashMap::new();
+        for (cube, tile) in world.iter() {
+            let Some(army) = &tile.army else {continue};
+            if !world.diplomacy.can_attack_owner(army.owner_index, Some(*own_player_index)) {continue}
+            let strength = army.combat_strength();
+            for target in world.get_reachable_cubes(cube) {
+                let threat = threats.entry(target).or_insert(0);
+                *threat = (*threat).max(strength);
+            }
+        }
+        ThreatMap{threats}
+    }
+    pub fn threat(&self, cube: &Cube<i32>) -> i32 {
+        *self.threats.get(cube).unwrap_or(&0)
+    }
+}
+
+/// How many moves every cube is from the nearest tile the AI could capture.
+/// Water can only be entered from a port city or from water, and owned airports are one move apart.
+pub struct FrontMap {
+    distances: HashMap<Cube<i32>, i32>,
+}
+
+impl FrontMap {
+    pub fn new(own_player_index: &usize, world: &World) -> Self {
+        let mut distances = HashMap::new();
+        let mut frontier = VecDeque::new();
+        for (cube, tile) in world.iter() {
+            let is_capturable = tile.owner_index != Some(*own_player_index)
+                && !matches!(tile.category, TileCategory::Water)
+                && world.diplomacy.can_attack_owner(Some(*own_player_index), tile.owner_index);
+            if is_capturable {
+                distances.insert(*cube, 0);
+                frontier.push_back(*cube);
+            }
+        }
+        let empty_set = HashSet::new();
+        let own_cubes = world.cubes_by_ownership.get(own_player_index).unwrap_or(&empty_set);
+        let is_own_airport = |cube: &Cube<i32>| world.cubes_with_airport.contains(cube) && own_cubes.contains(cube);
+
+        // Searches backwards, from every cube to the cubes an army could move to it from
+        while let Some(current) = frontier.pop_front() {
+            let distance = distances[&current];
+            let is_water = world.get(&current).is_some_and(|tile| matches!(tile.category, TileCategory::Water));
+            let mut origins: Vec<Cube<i32>> = current.disc(1).into_iter().filter(|neighbour| {
+                world.get(neighbour).is_some_and(|tile| !is_water
+                    || matches!(tile.category, TileCategory::Water)
+                    || tile.locality.as_ref().is_some_and(|l| matches!(l.category, LocalityCategory::PortCity)))
+            }).collect();
+            if is_own_airport(&current) {
+                origins.extend(own_cubes.iter().filter(|cube| is_own_airport(cube)));
+            }
+            for origin in origins {
+                if !distances.contains_key(&origin) {
+                    distances.insert(origin, distance + 1);
+                    frontier.push_back(origin);
+                }
+            }
+        }
+        FrontMap{distances}
+    }
+    pub fn distance(&self, cube: &Cube<i32>) -> Option<i32> {
+        self.distances.get(cube).copied()
+    }
+}
+
+/// What the AI knows about the world before scoring its moves.
+pub struct Assessment {
+    pub threats: ThreatMap,
+    pub fronts: FrontMap,
+}
+
+impl Assessment {
+    pub fn new(own_player_index: &usize, world: &World) -> Self {
+        Assessment{threats: ThreatMap::new(own_player_index, world), fronts: FrontMap::new(own_player_index, world)}
+    }
+}
+
+/// The components a move's score is made of, to explain the AI's decisions.
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct ScoreBreakdown {
+    pub combat: i32,
+    pub tile: i32,
+    pub border: i32,
+    pub threat: i32,
+    pub redeployment: i32,
+}
+
+impl ScoreBreakdown {
+    pub fn total(&self) -> i32 {
+        self.combat + self.tile + self.border + self.threat + self.redeployment
+    }
+}
+
+/// A move the AI considered, and whether it ordered it.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConsideredMove {
+    pub origin: Cube<i32>,
+    pub target: Cube<i32>,
+    pub score: i32,
+    pub breakdown: ScoreBreakdown,
+    pub chosen: bool,
+}
+
+/// Everything the AI considered during one turn, for the debug overlay.
+#[derive(Serialize, Clone, Debug)]
+pub struct TurnExplanation {
+    pub turn: i32,
+    pub player: usize,
+    pub moves: Vec<ConsideredMove>,
+}
+
+#[derive(Debug, Clone)]
 pub struct ScoredMove {
-    score: i32,
+    pub score: i32,
     pub origin: Cube<i32>,
     pub target: Cube<i32>,
 }
@@ -50,14 +210,179 @@ pub const DEFAULT_SCORES: Scores = Scores {
     capital: 100,
 };
 
+// Trades armies freely, valuing enemy manpower destroyed above land.
+pub const AGGRESSIVE_SCORES: Scores = Scores {
+    water: 0,
+    manpower: 3,
+    farmland: 1,
+    port_city: 8,
+    airport: 9,
+    city: 10,
+    satellite_capital: 15,
+    capital: 100,
+};
+
+// Grabs as much land and as many cities as it can.
+pub const EXPANSIONIST_SCORES: Scores = Scores {
+    water: 0,
+    manpower: 1,
+    farmland: 3,
+    port_city: 12,
+    airport: 12,
+    city: 15,
+    satellite_capital: 20,
+    capital: 100,
+};
+
+// Avoids losing its armies, only taking what comes cheap.
+pub const TURTLE_SCORES: Scores = Scores {
+    water: 0,
+    manpower: 4,
+    farmland: 0,
+    port_city: 6,
+    airport: 6,
+    city: 8,
+    satellite_capital: 12,
+    capital: 80,
+};
+
+// Ignores nearly everything but the enemy capitals.
+pub const CAPITAL_RUSHER_SCORES: Scores = Scores {
+    water: 0,
+    manpower: 1,
+    farmland: 0,
+    port_city: 4,
+    airport: 6,
+    city: 5,
+    satellite_capital: 30,
+    capital: 300,
+};
+
+// Score for every tile an army gets closer to the front, and for merging two stacks into one.
+const REDEPLOYMENT_SCORE_PER_TILE: i32 = 1;
+const REGROUP_SCORE: i32 = 2;
+
+// The maximum noise added to move scores by an easy AI, and how often it forgets to use an order.
+const EASY_SCORE_NOISE: i32 = 15;
+const EASY_SKIP_CHANCE: f32 = 0.3;
+
+/// How well an AI plays. Easy AIs pick sub-optimal moves, hard ones search whole turns ahead.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, EnumIter)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Difficulty::Easy => write!(f, "Easy"),
+            Difficulty::Normal => write!(f, "Normal"),
+            Difficulty::Hard => write!(f, "Hard"),
+        }
+    }
+}
+
+/// What an AI values, expressed as a preset of scores.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, EnumIter)]
+pub enum Personality {
+    #[default]
+    Balanced,
+    Aggressive,
+    Expansionist,
+    Turtle,
+    CapitalRusher,
+}
+
+impl Personality {
+    pub fn scores(&self) -> Scores {
+        match self {
+            Personality::Balanced => DEFAULT_SCORES,
+            Personality::Aggressive => AGGRESSIVE_SCORES,
+            Personality::Expansionist => EXPANSIONIST_SCORES,
+            Personality::Turtle => TURTLE_SCORES,
+            Personality::CapitalRusher => CAPITAL_RUSHER_SCORES,
+        }
+    }
+}
+
+impl Display for Personality {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Personality::Balanced => write!(f, "Balanced"),
+            Personality::Aggressive => write!(f, "Aggressive"),
+            Personality::Expansionist => write!(f, "Expansionist"),
+            Personality::Turtle => write!(f, "Turtle"),
+            Personality::CapitalRusher => write!(f, "Capital Rusher"),
+        }
+    }
+}
+
+/// How an AI plans its turn.
+/// Greedy scores every army's best single move against the world as it is at the start of the turn.
+/// BeamSearch simulates whole sequences of orders on cloned worlds, keeping the `width` best
+/// sequences after each order, and stops early once it has simulated `max_nodes` orders
+/// or spent `time_budget` seconds. Without a time budget, the search replays the same from the same seed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub enum Strategy {
+    #[default]
+    Greedy,
+    BeamSearch {
+        width: usize,
+        branching: usize,
+        max_nodes: usize,
+        time_budget: Option<f64>,
+    },
+}
+
+impl Strategy {
+    /// The same strategy without a time budget, so that the result does not depend on the speed of the machine.
+    pub fn untimed(self) -> Strategy {
+        match self {
+            Strategy::BeamSearch{width, branching, max_nodes, ..} => Strategy::BeamSearch{width, branching, max_nodes, time_budget: None},
+            Strategy::Greedy => Strategy::Greedy,
+        }
+    }
+}
+
+pub const DEFAULT_BEAM_SEARCH: Strategy = Strategy::BeamSearch {
+    width: 4,
+    branching: 8,
+    max_nodes: 2000,
+    time_budget: Some(0.5),
+};
+
+// A partial sequence of orders explored by the beam search.
+struct SearchNode {
+    world: World,
+    orders: Vec<ScoredMove>,
+    value: i32,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AI {
     pub scores: Scores,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub personality: Personality,
 }
 
 impl AI {
     pub fn new() -> Self {
-        AI{scores: DEFAULT_SCORES}
+        AI::from_preset(Difficulty::Normal, Personality::Balanced)
+    }
+    /// Creates an AI whose scores come from the personality, and whose strategy comes from the difficulty.
+    pub fn from_preset(difficulty: Difficulty, personality: Personality) -> Self {
+        let strategy = match difficulty {
+            Difficulty::Easy | Difficulty::Normal => Strategy::Greedy,
+            Difficulty::Hard => DEFAULT_BEAM_SEARCH,
+        };
+        AI{scores: personality.scores(), strategy, difficulty, personality}
     }
     fn match_tile_category_score(&self, category: &TileCategory) -> i32 {
         match category {
@@ -112,33 +437,104 @@ impl AI {
         score
     }
 
-    /// Calculates and returns a score value for a given move.
-    fn calculate_score(&self, own_player_index: &usize, world: &World, origin_cube: &Cube<i32>, target_cube: &Cube<i32>) -> i32 {
+    /// Calculates the defensive score component, from what the enemy can do next turn.
+    /// Garrisoning threatened localities and pulling weak stacks out of reach score positively,
+    /// leaving a threatened locality empty or moving into a stronger enemy's reach negatively.
+    fn calculate_threat_score(&self, own_player_index: &usize, world: &World, threats: &ThreatMap, origin_cube: &Cube<i32>, target_cube: &Cube<i32>) -> i32 {
         let origin = world.get(origin_cube).unwrap();
         let target = world.get(target_cube).unwrap();
+        let origin_army = origin.army.as_ref().unwrap();
+        let strength = origin_army.combat_strength();
+        let army_value = origin_army.manpower * self.scores.manpower / 10;
+        let origin_threat = threats.threat(origin_cube);
+        let target_threat = threats.threat(target_cube);
         let mut score = 0;
+
+        // Leaving a locality empty hands it to the enemy, unless the garrison could not have held it anyway
+        if let Some(locality) = &origin.locality {
+            if origin_threat > 0 {
+                let value = self.match_locality_category_score(&locality.category);
+                score -= if strength >= origin_threat {value} else {value / 2};
+            }
+        }
+
+        let is_own_target = target.owner_index == Some(*own_player_index);
+        let resulting_strength = match &target.army {
+            Some(army) if is_own_target => strength + army.combat_strength(),
+            Some(army) => strength - army.combat_strength(),
+            None => strength,
+        };
+        if target_threat > 0 {
+            if let Some(locality) = &target.locality {
+                let garrison = target.army.as_ref().map_or(0, |army| army.combat_strength());
+                if is_own_target && garrison < target_threat {
+                    let value = self.match_locality_category_score(&locality.category);
+                    score += value * resulting_strength.min(target_threat) / target_threat;
+                }
+            }
+            if resulting_strength < target_threat {
+                score -= army_value;
+            }
+        }
+        // Retreating a stack that would be destroyed where it stands
+        if origin.locality.is_none() && origin_threat > strength && resulting_strength >= target_threat {
+            score += army_value;
+        }
+        score
+    }
+
+    /// Calculates the strategic redeployment score component, so that armies with nothing
+    /// to capture move towards the front, merge their stacks, and fly or sail where needed.
+    fn calculate_redeployment_score(&self, own_player_index: &usize, world: &World, fronts: &FrontMap, origin_cube: &Cube<i32>, target_cube: &Cube<i32>) -> i32 {
+        let mut score = match (fronts.distance(origin_cube), fronts.distance(target_cube)) {
+            (Some(from), Some(to)) => (from - to) * REDEPLOYMENT_SCORE_PER_TILE,
+            _ => 0,
+        };
+        let origin_army = world.get(origin_cube).unwrap().army.as_ref().unwrap();
+        let target = world.get(target_cube).unwrap();
+        if let Some(target_army) = &target.army {
+            let is_own_army = target.owner_index == Some(*own_player_index);
+            if is_own_army && origin_army.manpower + target_army.manpower <= MAX_STACK_SIZE {
+                score += REGROUP_SCORE;
+            }
+        }
+        score
+    }
+
+    /// Calculates the score of a given move, split into its components.
+    fn calculate_score_breakdown(&self, own_player_index: &usize, world: &World, assessment: &Assessment, origin_cube: &Cube<i32>, target_cube: &Cube<i32>) -> ScoreBreakdown {
+        let origin = world.get(origin_cube).unwrap();
+        let target = world.get(target_cube).unwrap();
+        let mut score = ScoreBreakdown::default();
         if target.owner_index != Some(*own_player_index) {
             match &target.army {
                 Some(target_army) => {
                     let origin_army = origin.army.as_ref().unwrap();
-                    score += self.calculate_combat_score(&world, &origin_cube, target_cube);
+                    score.combat = self.calculate_combat_score(&world, &origin_cube, target_cube);
                     let diff = origin_army.combat_strength() - target_army.combat_strength();
                     if diff > 0 {
-                        score += self.match_tile_score(&target);
-                        score += self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
+                        score.tile = self.match_tile_score(&target);
+                        score.border = self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
                     }
                 }
                 None => {
-                    score += self.match_tile_score(&target);
-                    score += self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
+                    score.tile = self.match_tile_score(&target);
+                    score.border = self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
                 }
             }
         } else {
-            score += self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
+            score.border = self.calculate_extended_border_score(&own_player_index, &world, &target_cube);
         }
+        score.threat = self.calculate_threat_score(own_player_index, world, &assessment.threats, origin_cube, target_cube);
+        score.redeployment = self.calculate_redeployment_score(own_player_index, world, &assessment.fronts, origin_cube, target_cube);
         score
     }
 
+    /// Calculates and returns a score value for a given move.
+    fn calculate_score(&self, own_player_index: &usize, world: &World, assessment: &Assessment, origin_cube: &Cube<i32>, target_cube: &Cube<i32>) -> i32 {
+        self.calculate_score_breakdown(own_player_index, world, assessment, origin_cube, target_cube).total()
+    }
+
     /// Creates a subset of the game world containing only entries with own armies.
     fn create_owned_armies_world_subset(&self, own_player_index: &usize, world: &World) -> HashSet<Cube<i32>> {
         // TODO: Decouple subset of tiles containing armies from useful moves subset.
@@ -146,7 +542,8 @@ impl AI {
         for cube in world.cubes_by_ownership.get(own_player_index).unwrap().iter() {
             let tile = world.get(cube).unwrap();
             if let Some(army) = &tile.army {
-                if army.can_move && world.is_there_capturable_tile_within_range(cube) { //can_move redundant if all created once a turn?
+                // Armies with nothing to capture may still be needed for defence, or elsewhere on the front
+                if army.can_move { //can_move redundant if all created once a turn?
                     result.insert(*cube);
                 }
             }
@@ -155,14 +552,17 @@ impl AI {
     }
 
     /// Explores the scores a tile containing an army can achieve for all valid targets.
-    fn explore_targets(&self, own_player_index: &usize, world: &World, origin: &Cube<i32>) -> Option<ScoredMove> {
+    fn explore_targets(&self, own_player_index: &usize, world: &World, assessment: &Assessment, origin: &Cube<i32>) -> Option<ScoredMove> {
         //let mut results = Vec::new();
-        let valid_targets = world.get_reachable_cubes(&origin);
-        let prev_score = 0;
+        let valid_targets = world.get_all_legal_moves(&origin, own_player_index);
+        let mut best_score = 0;
         let mut result = None;
         for target in valid_targets {
-            let score = self.calculate_score(&own_player_index, world, &origin, &target);
-            if score > prev_score {result = Some(ScoredMove{score, origin: *origin, target});}
+            let score = self.calculate_score(&own_player_index, world, assessment, &origin, &target);
+            if score > best_score {
+                best_score = score;
+                result = Some(ScoredMove{score, origin: *origin, target});
+            }
             // let element = ScoredMove{score, origin: *origin, target};
             // results.push(element);
             //return result; // can only move each army once, how to handle?
@@ -172,10 +572,11 @@ impl AI {
 
     /// Score every likely useful player move.
     fn create_target_list(&self, own_player_index: &usize, world: &World) -> Vec<ScoredMove> {
-        let subset = self.create_owned_armies_world_subset(&own_player_index, &world); // this only returns 'useful' armies
+        let assessment = Assessment::new(own_player_index, world);
+        let subset = self.create_owned_armies_world_subset(&own_player_index, &world);
         let mut target_list = vec!();
         for origin in subset {
-            target_list.push(self.explore_targets(&own_player_index, &world, &origin))
+            target_list.push(self.explore_targets(&own_player_index, &world, &assessment, &origin))
             //target_list.append(&mut self.explore_targets(&own_player_index, &world, &origin))
         }
         target_list.into_iter().flatten().collect::<Vec<ScoredMove>>()
@@ -186,10 +587,136 @@ impl AI {
         // TODO: Return a lazy generator instead. -> std::slice::Iter<'_, ScoredMove>
         let mut target_list = self.create_target_list(&own_player_index, &world);
         target_list.sort_by_key(|scored_move| Reverse(scored_move.score));
-        if target_list.is_empty() {println!("empty target list")};
+        if target_list.is_empty() && world.is_logged() {println!("empty target list")};
         target_list
     }
 
+    /// Plans the orders for this turn, according to the AI's strategy.
+    pub fn plan_turn(&self, own_player_index: &usize, world: &World, actions: i32) -> Vec<ScoredMove> {
+        let orders = match self.strategy {
+            Strategy::Greedy => self.generate_targets(own_player_index, world),
+            Strategy::BeamSearch{width, branching, max_nodes, time_budget} => {
+                let depth = actions.max(0) as usize;
+                self.beam_search(own_player_index, world, depth, width, branching, max_nodes, time_budget)
+            }
+        };
+        match self.difficulty {
+            Difficulty::Easy => AI::blunder(orders),
+            Difficulty::Normal | Difficulty::Hard => orders,
+        }
+    }
+
+    /// Makes an easy AI play sub-optimally: scores are blurred by noise, and some orders are forgotten.
+    fn blunder(orders: Vec<ScoredMove>) -> Vec<ScoredMove> {
+        let mut orders: Vec<ScoredMove> = rng::with_rng(|rng| orders.into_iter().filter_map(|mut scored_move| {
+            if rng.gen::<f32>() < EASY_SKIP_CHANCE {return None}
+            scored_move.score += rng.gen_range(-EASY_SCORE_NOISE..=EASY_SCORE_NOISE);
+            Some(scored_move)
+        }).collect());
+        orders.sort_by_key(|scored_move| Reverse(scored_move.score));
+        orders
+    }
+
+    /// Evaluates a whole world state from the point of view of the player.
+    /// Tiles and armies held by the player count for it, those held by anyone else count against it.
+    /// Own localities that an enemy army could take next turn count for half.
+    fn evaluate_world(&self, own_player_index: &usize, world: &World) -> i32 {
+        let threats = ThreatMap::new(own_player_index, world);
+        let exposure: i32 = world.cubes_by_ownership.get(own_player_index).map_or(0, |cubes| {
+            cubes.iter().filter_map(|cube| {
+                let tile = world.get(cube).unwrap();
+                let locality = tile.locality.as_ref()?;
+                let garrison = tile.army.as_ref().map_or(0, |army| army.combat_strength());
+                let threat = threats.threat(cube);
+                (threat > 0 && garrison < threat).then(|| self.match_locality_category_score(&locality.category) / 2)
+            }).sum()
+        });
+        let value: i32 = world.values().map(|tile| {
+            let army_value = tile.army.as_ref().map_or(0, |army| army.combat_strength() * self.scores.manpower / 10);
+            let army_owner = tile.army.as_ref().and_then(|army| army.owner_index);
+            let army_sign = match army_owner {
+                Some(index) if index == *own_player_index => 1,
+                Some(_) => -1,
+                None => 0,
+            };
+            let tile_sign = match tile.owner_index {
+                Some(index) if index == *own_player_index => 1,
+                Some(_) => -1,
+                None => 0,
+            };
+            tile_sign * self.match_tile_score(tile) + army_sign * army_value
+        }).sum();
+        value - exposure
+    }
+
+    /// Lists the most promising legal orders in the given world, ranked by their greedy score.
+    fn candidate_moves(&self, own_player_index: &usize, world: &World, branching: usize) -> Vec<ScoredMove> {
+        let assessment = Assessment::new(own_player_index, world);
+        let mut candidates: Vec<ScoredMove> = legal_orders(own_player_index, world).into_iter().map(|(origin, target)| {
+            let score = self.calculate_score(own_player_index, world, &assessment, &origin, &target);
+            ScoredMove{score, origin, target}
+        }).collect();
+        candidates.sort_by_key(|scored_move| Reverse(scored_move.score));
+        candidates.truncate(branching);
+        candidates
+    }
+
+    /// Scores every legal move of the player, marking the ones that were ordered.
+    pub fn explain_turn(&self, own_player_index: &usize, world: &World, turn: i32, orders: &[Order]) -> TurnExplanation {
+        let assessment = Assessment::new(own_player_index, world);
+        let mut moves: Vec<ConsideredMove> = legal_orders(own_player_index, world).into_iter().map(|(origin, target)| {
+            let breakdown = self.calculate_score_breakdown(own_player_index, world, &assessment, &origin, &target);
+            let chosen = orders.iter().any(|order| order.origin == origin && order.target == target);
+            ConsideredMove{origin, target, score: breakdown.total(), breakdown, chosen}
+        }).collect();
+        moves.sort_by_key(|considered| Reverse(considered.score));
+        TurnExplanation{turn, player: *own_player_index, moves}
+    }
+
+    /// Searches for the best sequence of up to `depth` orders, re-evaluating the world after every simulated order.
+    fn beam_search(&self, own_player_index: &usize, world: &World, depth: usize, width: usize, branching: usize, max_nodes: usize, time_budget: Option<f64>) -> Vec<ScoredMove> {
+        let start_time = date::now();
+        let mut root_world = world.clone();
+        root_world.is_simulation = true;
+        let root_value = self.evaluate_world(own_player_index, &root_world);
+        let mut beam = vec!(SearchNode{world: root_world, orders: vec!(), value: root_value});
+        let (mut best_value, mut best_orders) = (root_value, vec!());
+        let mut nodes = 0;
+        let mut is_budget_exhausted = false;
+
+        for _ in 0..depth {
+            let mut children = vec!();
+            'layer: for node in beam.iter() {
+                for candidate in self.candidate_moves(own_player_index, &node.world, branching) {
+                    if nodes >= max_nodes || time_budget.is_some_and(|budget| date::now() - start_time > budget) {
+                        is_budget_exhausted = true;
+                        break 'layer;
+                    }
+                    nodes += 1;
+                    let mut child_world = node.world.clone();
+                    child_world.execute_army_order(&candidate.origin, &candidate.target);
+                    let value = self.evaluate_world(own_player_index, &child_world);
+                    let mut orders = node.orders.clone();
+                    orders.push(ScoredMove{score: value - node.value, origin: candidate.origin, target: candidate.target});
+                    children.push(SearchNode{world: child_world, orders, value});
+                }
+            }
+            if children.is_empty() {break}
+            children.sort_by_key(|node| Reverse(node.value));
+            children.truncate(width.max(1));
+            if children[0].value > best_value {
+                best_value = children[0].value;
+                best_orders = children[0].orders.clone();
+            }
+            beam = children;
+            if is_budget_exhausted {break}
+        }
+        if world.is_logged() {
+            println!("beam search: {} orders simulated in {:.3}s, value {} -> {}", nodes, date::now() - start_time, root_value, best_value);
+        }
+        best_orders
+    }
+
     // def controller(game, target):
     //     """Attempts to calculate the most optimal move and performs the necessary
     //     click_on_tile() Player method calls to execute them.
@@ -211,4 +738,19 @@ impl AI {
 
     // # def pick_actions():
     // #     """Pick """
-}
\ No newline at end of file
+}
+
+/// Every legal order of every army of the player that can still move.
+fn legal_orders(own_player_index: &usize, world: &World) -> Vec<(Cube<i32>, Cube<i32>)> {
+    let mut orders = vec!();
+    if let Some(cubes) = world.cubes_by_ownership.get(own_player_index) {
+        for origin in cubes {
+            let tile = world.get(origin).unwrap();
+            if !tile.army.as_ref().is_some_and(|army| army.can_move) {continue}
+            for target in world.get_all_legal_moves(origin, own_player_index) {
+                orders.push((*origin, target));
+            }
+        }
+    }
+    orders
+}
@@ -20,7 +20,7 @@ use std::ops::SubAssign;
 
 use serde::{Serialize, Deserialize};
 
-#[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Cube<T>(T, T);
 
 #[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
@@ -309,12 +309,6 @@ pub const DIRECTIONS: [Cube<i32>; 6] = [
     Cube(-1, 1), Cube(-1, 0), Cube(0, -1),
 ];
 
-// Because Rev<Iter> is a different type to Iter
-pub const REV_DIRECTIONS: [Cube<i32>; 6] = [
-    Cube(0, -1), Cube(-1, 0), Cube(-1, 1),
-    Cube(0, 1), Cube(1, 0), Cube(1, -1),
-];
-
 // impl<T> Point<T> where T: Copy + Signed + Div<i32, Output=T> {
     // fn get_neighbour(&self, direction: usize) -> Point<T> {
     //     *self + DIRECTIONS[direction]
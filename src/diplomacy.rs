@@ -0,0 +1,186 @@
+/// The diplomacy module keeps track of the relations between players.
+/// Every pair of players is at war unless they have agreed on a pact.
+
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::World;
+
+// An AI accepts a pact from a player at least this strong relative to itself.
+const AI_MIN_STRENGTH_RATIO_NON_AGGRESSION: f32 = 0.5;
+// An AI offers peace to a player this much stronger than itself.
+const AI_APPEASEMENT_STRENGTH_RATIO: f32 = 1.5;
+const STRENGTH_PER_LOCALITY: i32 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Relation {
+    War,
+    NonAggression,
+    Alliance,
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            Relation::War => write!(f, "War"),
+            Relation::NonAggression => write!(f, "Non-aggression Pact"),
+            Relation::Alliance => write!(f, "Alliance"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Pact {
+    pub between: [usize; 2],
+    pub relation: Relation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Proposal {
+    pub from: usize,
+    pub to: usize,
+    pub relation: Relation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Diplomacy {
+    pacts: Vec<Pact>,
+    pub proposals: Vec<Proposal>,
+}
+
+impl Diplomacy {
+    pub fn new() -> Self {
+        Diplomacy::default()
+    }
+
+    fn find_pact(&self, a: usize, b: usize) -> Option<usize> {
+        self.pacts.iter().position(|pact| pact.between == [a, b] || pact.between == [b, a])
+    }
+
+    /// Returns the relation between two players. A player is always allied with itself.
+    pub fn relation(&self, a: usize, b: usize) -> Relation {
+        if a == b {return Relation::Alliance}
+        match self.find_pact(a, b) {
+            Some(index) => self.pacts[index].relation,
+            None => Relation::War,
+        }
+    }
+
+    pub fn set_relation(&mut self, a: usize, b: usize, relation: Relation) {
+        if a == b {return}
+        let index = self.find_pact(a, b);
+        match (index, relation) {
+            (Some(index), Relation::War) => {self.pacts.remove(index);},
+            (Some(index), _) => self.pacts[index].relation = relation,
+            (None, Relation::War) => {},
+            (None, _) => self.pacts.push(Pact{between: [a, b], relation}),
+        }
+        // Any pending proposals between the pair are now outdated
+        self.proposals.retain(|p| !((p.from == a && p.to == b) || (p.from == b && p.to == a)));
+    }
+
+    pub fn are_allied(&self, a: usize, b: usize) -> bool {
+        matches!(self.relation(a, b), Relation::Alliance)
+    }
+
+    /// Whether the armies of one player may attack or capture the tiles of another.
+    pub fn can_attack(&self, a: usize, b: usize) -> bool {
+        a != b && matches!(self.relation(a, b), Relation::War)
+    }
+
+    /// Same as can_attack(), but for optional owners. Unowned tiles can always be captured.
+    pub fn can_attack_owner(&self, a: Option<usize>, b: Option<usize>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => self.can_attack(a, b),
+            (_, None) => true,
+            (None, Some(_)) => false,
+        }
+    }
+
+    pub fn allies_of(&self, player_index: usize) -> Vec<usize> {
+        self.pacts.iter().filter(|pact| matches!(pact.relation, Relation::Alliance)).filter_map(|pact| {
+            match pact.between {
+                [a, b] if a == player_index => Some(b),
+                [a, b] if b == player_index => Some(a),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Queues a proposal, replacing any older one between the same players.
+    pub fn propose(&mut self, from: usize, to: usize, relation: Relation) {
+        if from == to || self.relation(from, to) == relation {return}
+        self.proposals.retain(|p| !(p.from == from && p.to == to));
+        self.proposals.push(Proposal{from, to, relation});
+    }
+
+    pub fn proposals_to(&self, player_index: usize) -> Vec<Proposal> {
+        self.proposals.iter().filter(|p| p.to == player_index).copied().collect()
+    }
+
+    pub fn accept(&mut self, proposal: Proposal) {
+        println!("Player {} accepts the {} proposed by Player {}", proposal.to, proposal.relation, proposal.from);
+        self.set_relation(proposal.from, proposal.to, proposal.relation);
+    }
+
+    pub fn decline(&mut self, proposal: Proposal) {
+        println!("Player {} declines the {} proposed by Player {}", proposal.to, proposal.relation, proposal.from);
+        self.proposals.retain(|p| !(p.from == proposal.from && p.to == proposal.to));
+    }
+
+    /// Drops every pending proposal made by or to the player.
+    pub fn withdraw_proposals(&mut self, player_index: usize) {
+        self.proposals.retain(|p| p.from != player_index && p.to != player_index);
+    }
+
+    /// Breaks any pact between the two players.
+    pub fn declare_war(&mut self, from: usize, to: usize) {
+        println!("Player {} declares war on Player {}", from, to);
+        self.set_relation(from, to, Relation::War);
+    }
+}
+
+/// A rough measure of a player's military and economic strength.
+pub fn player_strength(world: &World, player_index: usize) -> i32 {
+    world.cubes_by_ownership.get(&player_index).map_or(0, |cubes| {
+        cubes.iter().map(|cube| {
+            let tile = world.get(cube).unwrap();
+            let manpower = tile.army.as_ref().map_or(0, |army| army.manpower);
+            let locality = if tile.locality.is_some() {STRENGTH_PER_LOCALITY} else {0};
+            manpower + locality
+        }).sum()
+    })
+}
+
+/// Decides whether an AI player accepts a proposal, based on the relative strength of the players.
+/// Pacts are accepted from players strong enough to be worth not fighting.
+/// Alliances are only accepted when a third player is stronger than the both of them.
+pub fn ai_accepts(world: &World, proposal: &Proposal, player_count: usize) -> bool {
+    let own_strength = player_strength(world, proposal.to) as f32;
+    let proposer_strength = player_strength(world, proposal.from) as f32;
+    match proposal.relation {
+        Relation::War => true,
+        Relation::NonAggression => proposer_strength >= own_strength * AI_MIN_STRENGTH_RATIO_NON_AGGRESSION,
+        Relation::Alliance => {
+            let strongest_rival = (0..player_count)
+                .filter(|&index| index != proposal.to && index != proposal.from)
+                .map(|index| player_strength(world, index) as f32)
+                .fold(0., f32::max);
+            strongest_rival > own_strength && strongest_rival > proposer_strength
+        }
+    }
+}
+
+/// Returns the players an AI wants to make peace with: those much stronger than itself.
+pub fn ai_proposals(world: &World, diplomacy: &Diplomacy, player_index: usize, player_count: usize) -> Vec<Proposal> {
+    let own_strength = player_strength(world, player_index) as f32;
+    (0..player_count).filter(|&index| {
+        index != player_index
+        && matches!(diplomacy.relation(player_index, index), Relation::War)
+        && player_strength(world, index) as f32 > own_strength * AI_APPEASEMENT_STRENGTH_RATIO
+    }).map(|index| Proposal{from: player_index, to: index, relation: Relation::NonAggression}).collect()
+}
@@ -4,8 +4,10 @@
 // import worldgen
 // import playergen
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::option::Option;
@@ -16,19 +18,29 @@ use std::fmt::Result;
 
 use serde::Deserialize;
 use serde::Serialize;
+use macroquad::miniquad::date;
 
 use crate::cubic::*;
 use crate::Army;
 use crate::Player;
 use crate::World;
 use crate::mquad::Assets;
+use crate::agent::Agent;
+use crate::agent::Order;
+use crate::agent::Bot;
+use crate::ai::AI;
+use crate::ai::ScoredMove;
+use crate::ai::TurnExplanation;
+use crate::diplomacy;
+use crate::diplomacy::Diplomacy;
+use crate::diplomacy::Relation;
 use crate::world::MAX_STACK_SIZE;
 use crate::world::ACTIONS_PER_TURN;
 
 use crate::world::TileCategory;
 use crate::world::gen::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum VictoryCondition {
     Elimination,
     Territory(f32),
@@ -36,16 +48,23 @@ pub enum VictoryCondition {
 }
 
 impl VictoryCondition {
-    fn check_territory(world: &World, player_index: usize, target_percentage: f32) -> bool {
+    fn check_territory(world: &World, coalition: &[usize], target_percentage: f32) -> bool {
         let world_total = world.len();
-        let player_total = world.iter().filter(|&(_, tile)| tile.owner_index == Some(player_index)).count();
-        player_total as f32 / world_total as f32 >= target_percentage
+        let coalition_total = world.iter().filter(|&(_, tile)| tile.owner_index.is_some_and(|i| coalition.contains(&i))).count();
+        coalition_total as f32 / world_total as f32 >= target_percentage
+    }
+    fn check_elimination(world: &World, coalition: &[usize]) -> bool {
+        world.cubes_by_ownership.iter().all(|(index, cubes)| coalition.contains(index) || cubes.is_empty())
+    }
+    fn check_capture_and_hold(world: &World, coalition: &[usize], cubes: &HashSet<Cube<i32>>) -> bool {
+        cubes.iter().all(|cube| world.get(cube).is_some_and(|tile| tile.owner_index.is_some_and(|i| coalition.contains(&i))))
     }
-    pub fn check(&self, world: &World, player_index: usize) -> bool {
+    /// Checks whether a coalition of players, sharing their victory, has won.
+    pub fn check(&self, world: &World, coalition: &[usize]) -> bool {
         match self {
-            Self::Elimination => unimplemented!(),
-            Self::Territory(x) => VictoryCondition::check_territory(&world, player_index, *x),
-            _ => unimplemented!(),
+            Self::Elimination => VictoryCondition::check_elimination(&world, coalition),
+            Self::Territory(x) => VictoryCondition::check_territory(&world, coalition, *x),
+            Self::CaptureAndHold(cubes) => VictoryCondition::check_capture_and_hold(&world, coalition, cubes),
         }
     }
 }
@@ -56,6 +75,79 @@ pub struct Game {
     //pub current_player: &'a Player, // change it to a function?
     pub world: World,
     pub victory_condition: VictoryCondition,
+    // When enabled, players and their AI only see what their territory and armies can see.
+    #[serde(default)]
+    pub fog_of_war: bool,
+    #[serde(default)]
+    pub diplomacy: Diplomacy,
+    // Players who have won the game, sharing the victory with their allies.
+    #[serde(default)]
+    pub winners: Vec<usize>,
+    // Whether losing the capital makes a player capitulate to the captor.
+    #[serde(default = "default_capitulation")]
+    pub capitulation: bool,
+    // When enabled, the AI explains every turn it plays, which is drawn on the map and dumped to JSON.
+    #[serde(skip)]
+    pub ai_debug: bool,
+    #[serde(skip)]
+    pub ai_explanation: Option<TurnExplanation>,
+    // When enabled, human players are shown the moves the AI would make in their place.
+    #[serde(skip)]
+    pub advisor: bool,
+    #[serde(skip)]
+    pub advice: Vec<ScoredMove>,
+    // The turn and remaining actions the advice was computed for.
+    #[serde(skip)]
+    advice_for: Option<(i32, i32)>,
+    // Seconds between two AI orders, so that humans can follow them. Zero plays whole turns at once.
+    #[serde(default = "default_ai_order_delay")]
+    pub ai_order_delay: f64,
+    // Whether the camera pans to every AI order.
+    #[serde(default = "default_follow_ai")]
+    pub follow_ai: bool,
+    // Held down to play the queued AI orders without delay.
+    #[serde(skip)]
+    pub fast_forward: bool,
+    // The cube the camera should pan to, taken by the input handling which owns the camera.
+    #[serde(skip)]
+    pub camera_target: Option<Cube<i32>>,
+    #[serde(skip)]
+    ai_orders: Option<VecDeque<Order>>,
+    #[serde(skip)]
+    last_order_time: f64,
+    // Played without a window, e.g. in tournaments, logging nothing but the results.
+    #[serde(skip)]
+    pub headless: bool,
+}
+
+fn default_capitulation() -> bool {
+    true
+}
+
+fn default_ai_order_delay() -> f64 {
+    0.4
+}
+
+fn default_follow_ai() -> bool {
+    true
+}
+
+const AI_DEBUG_DIRECTORY: &str = "ai_debug";
+const ADVISOR_SUGGESTIONS: usize = 3;
+
+/// Saves what the AI considered during a turn to ai_debug/turn_<turn>_player_<index>.json.
+fn dump_ai_explanation(explanation: &TurnExplanation) {
+    if let Err(e) = std::fs::create_dir_all(AI_DEBUG_DIRECTORY) {
+        eprintln!("Error creating {}: {}", AI_DEBUG_DIRECTORY, e);
+        return
+    }
+    let path = format!("{}/turn_{}_player_{}.json", AI_DEBUG_DIRECTORY, explanation.turn, explanation.player);
+    match File::create(&path) {
+        Ok(file) => if let Err(e) = serde_json::to_writer_pretty(file, explanation) {
+            eprintln!("Error during serialization: {}", e);
+        },
+        Err(e) => eprintln!("Error creating {}: {}", path, e),
+    }
 }
 
 impl From<crate::map_editor::Editor> for Game {
@@ -67,7 +159,9 @@ impl From<crate::map_editor::Editor> for Game {
         //         players.push(player);
         //     }
         // );
-        crate::Game{turn: 1, players: value.players, world: value.world, victory_condition: VictoryCondition::Elimination}
+        let mut game = crate::Game::new(value.players, value.world, VictoryCondition::Elimination);
+        game.form_teams();
+        game
     }
 }
 
@@ -90,6 +184,30 @@ impl Game {
     // pub fn update(&mut self) {
     //     self._update()
     // }
+    pub fn new(players: Vec<Player>, world: World, victory_condition: VictoryCondition) -> Self {
+        Game {
+            turn: 1,
+            players,
+            world,
+            victory_condition,
+            fog_of_war: false,
+            diplomacy: Diplomacy::new(),
+            winners: vec!(),
+            capitulation: true,
+            ai_debug: false,
+            ai_explanation: None,
+            advisor: false,
+            advice: vec!(),
+            advice_for: None,
+            ai_order_delay: default_ai_order_delay(),
+            follow_ai: default_follow_ai(),
+            fast_forward: false,
+            camera_target: None,
+            ai_orders: None,
+            last_order_time: 0.,
+            headless: false,
+        }
+    }
     pub fn to_json(&self, path: &str) {
         let file = File::create(&path).expect("Failed to open the file.");
     
@@ -103,7 +221,9 @@ impl Game {
         let f = File::open(path)
             .expect("file should open read only");
 
-        serde_json::from_reader(f).expect("file should be proper JSON")
+        let mut game: Game = serde_json::from_reader(f).expect("file should be proper JSON");
+        game.sync_diplomacy();
+        game
     }
 
     pub fn current_player_index(&self) -> usize {
@@ -117,19 +237,171 @@ impl Game {
         let index = self.current_player_index();
         &self.players[index]
     }
-    pub fn init_world(&mut self, assets: &mut Assets) {
-        // let shape_gen = ShapeGen::Custom(assets.shape.clone());
-        let shape_gen = ShapeGen::Hexagonal(8);
-        let river_gen = RiverGen::Custom(assets.river.clone());
-        // let river_gen = RiverGen::Random(300, 0.3);
-        let localities_gen = LocalitiesGen::Random;
-        let capitals_gen = CapitalsGen::Random;
-        self.world.generate(
+    /// The human player whose view of the world is drawn: the current player,
+    /// or the last human to move if an AI is playing. None if there are no humans.
+    pub fn viewing_player_index(&self) -> Option<usize> {
+        let current_player_index = self.current_player_index();
+        let len = self.players.len();
+        (0..len).map(|offset| (current_player_index + len - offset) % len)
+                .find(|&index| self.players[index].ai.is_none())
+    }
+
+    /// The world as known to the given player. Without fog of war this is the world itself.
+    pub fn player_view(&self, player_index: Option<usize>) -> Cow<'_, World> {
+        match player_index {
+            Some(index) if self.fog_of_war => {
+                Cow::Owned(self.world.get_player_view(index, &self.players[index].last_seen))
+            }
+            _ => Cow::Borrowed(&self.world),
+        }
+    }
+
+    /// Updates every player's memory of the tiles they can currently see.
+    fn refresh_last_seen(&mut self) {
+        if !self.fog_of_war {return}
+        for (index, player) in self.players.iter_mut().enumerate() {
+            self.world.record_last_seen(index, &mut player.last_seen);
+        }
+    }
+
+    pub fn set_headless(&mut self, headless: bool) {
+        self.headless = headless;
+        self.world.is_headless = headless;
+    }
+
+    /// Copies the diplomatic relations into the world, where the movement rules can see them.
+    fn sync_diplomacy(&mut self) {
+        self.world.diplomacy = self.diplomacy.clone();
+    }
+
+    pub fn teams(&self) -> Vec<Option<usize>> {
+        self.players.iter().map(|player| player.team).collect()
+    }
+
+    pub fn are_teammates(&self, a: usize, b: usize) -> bool {
+        a != b && self.players[a].team.is_some() && self.players[a].team == self.players[b].team
+    }
+
+    /// Allies every player with its teammates. Called once the teams are declared.
+    pub fn form_teams(&mut self) {
+        for a in 0..self.players.len() {
+            for b in (a + 1)..self.players.len() {
+                if self.are_teammates(a, b) {
+                    self.diplomacy.set_relation(a, b, Relation::Alliance);
+                }
+            }
+        }
+        self.sync_diplomacy();
+    }
+
+    /// Proposes a pact to another player. AI players decide on the spot,
+    /// humans answer when it is their turn.
+    pub fn propose(&mut self, from: usize, to: usize, relation: Relation) {
+        if to >= self.players.len() || self.are_teammates(from, to) {return}
+        if matches!(relation, Relation::War) {
+            self.diplomacy.declare_war(from, to);
+        } else {
+            println!("Player {} proposes a {} to Player {}", from, relation, to);
+            self.diplomacy.propose(from, to, relation);
+            if self.players[to].ai.is_some() {
+                let proposal = diplomacy::Proposal{from, to, relation};
+                // Judged on what the AI can see, like its moves
+                let is_accepted = diplomacy::ai_accepts(&self.player_view(Some(to)), &proposal, self.players.len());
+                if is_accepted {
+                    self.diplomacy.accept(proposal);
+                } else {
+                    self.diplomacy.decline(proposal);
+                }
+            }
+        }
+        self.sync_diplomacy();
+    }
+
+    /// Accepts or declines the oldest proposal addressed to the current player.
+    pub fn answer_proposal(&mut self, accept: bool) {
+        let current_player_index = self.current_player_index();
+        if let Some(proposal) = self.diplomacy.proposals_to(current_player_index).first() {
+            if accept {
+                self.diplomacy.accept(*proposal);
+            } else {
+                self.diplomacy.decline(*proposal);
+            }
+            self.sync_diplomacy();
+        }
+    }
+
+    /// Marks a player as defeated. Its territory goes to the conqueror,
+    /// or is left without an owner if there is none.
+    fn defeat_player(&mut self, player_index: usize, conqueror_index: Option<usize>) {
+        let player = &mut self.players[player_index];
+        player.is_defeated = true;
+        player.selection = None;
+        player.skip_turn();
+        match conqueror_index {
+            Some(conqueror_index) => {
+                println!("{} capitulates to {}!", self.players[player_index], self.players[conqueror_index]);
+                self.world.surrender_to_player(&player_index, &conqueror_index);
+            }
+            None => {
+                println!("{} has been defeated!", self.players[player_index]);
+                self.world.abandon_player(&player_index);
+            }
+        }
+        self.diplomacy.withdraw_proposals(player_index);
+        self.sync_diplomacy();
+        self.refresh_last_seen();
+    }
+
+    /// Defeats players left without territory, and makes those who lost their capital capitulate.
+    fn check_defeats(&mut self) {
+        for player_index in 0..self.players.len() {
+            let player = &self.players[player_index];
+            if player.is_defeated {continue}
+            let has_territory = self.world.cubes_by_ownership.get(&player_index).is_some_and(|cubes| !cubes.is_empty());
+            let capital_owner = player.capital_pos.and_then(|cube| self.world.get(&cube)).and_then(|tile| tile.owner_index);
+            let has_lost_capital = player.capital_pos.is_some() && capital_owner != Some(player_index);
+            if !has_territory {
+                self.defeat_player(player_index, None);
+            } else if self.capitulation && has_lost_capital {
+                self.defeat_player(player_index, capital_owner);
+            }
+        }
+    }
+
+    /// The current player gives up, leaving its territory without an owner.
+    pub fn resign(&mut self) {
+        let current_player_index = self.current_player_index();
+        println!("{} resigns.", self.current_player());
+        self.defeat_player(current_player_index, None);
+    }
+
+    /// Returns the player, its teammates and its allies, who share a victory.
+    pub fn coalition_of(&self, player_index: usize) -> Vec<usize> {
+        let mut coalition: Vec<usize> = (0..self.players.len()).filter(|&index| {
+            index == player_index || self.are_teammates(player_index, index)
+        }).collect();
+        coalition.extend(self.diplomacy.allies_of(player_index));
+        coalition.sort();
+        coalition.dedup();
+        coalition
+    }
+
+    /// Returns the players sharing a victory, if any team or coalition of allies has won,
+    /// or is the last one standing.
+    fn check_victory(&self) -> Option<Vec<usize>> {
+        let remaining: Vec<usize> = (0..self.players.len()).filter(|&i| !self.players[i].is_defeated).collect();
+        (0..self.players.len()).filter(|&index| !self.players[index].is_defeated)
+            .map(|index| self.coalition_of(index))
+            .find(|coalition| {
+                remaining.iter().all(|i| coalition.contains(i))
+                || self.victory_condition.check(&self.world, coalition)
+            })
+    }
+
+    pub fn init_world(&mut self, assets: &Assets, settings: &WorldSettings) {
+        self.world.generate_with_settings(
+            settings,
             &mut self.players,
-            shape_gen,
-            river_gen,
-            localities_gen,
-            capitals_gen,
             &mut assets.locality_names.iter().map(|s| &**s).collect(),
             &assets.init_layout,
         );
@@ -147,8 +419,10 @@ impl Game {
         let target = self.world.get(target_cube).unwrap();
         let current_player_index = self.current_player_index();
         let current_player = self.current_player();
-        println!("current_selection: {:?}", current_player.selection);
-        println!("click: {:?}", target_cube);
+        if self.world.is_logged() {
+            println!("current_selection: {:?}", current_player.selection);
+            println!("click: {:?}", target_cube);
+        }
         // let is_target_selectable = if let Some(army) = &target.army {
         //     && target.owner_index == self.player_index(&world)
         //     && army.can_move
@@ -167,6 +441,8 @@ impl Game {
             let legal_moves = self.world.get_all_legal_moves(&selection, &current_player_index); // self.world.get_reachable_cubes(&selection);
             if legal_moves.contains(target_cube) { // && self.world.is_cube_targetable(&selection, target_cube) { // !matches!(target.category, TileCategory::Water) {
                 self.world.execute_army_order(&selection, &target_cube);
+                self.refresh_last_seen();
+                self.check_defeats();
                 let current_player = self.current_player_mut();
                 current_player.actions -= 1;
                 current_player.selection = None; // deselect
@@ -182,6 +458,7 @@ impl Game {
     }
     fn next_turn(&mut self) {
         let current_player_index = self.current_player_index();
+        self.ai_orders = None;
         self.current_player_mut().selection = None;
         self.current_player_mut().actions = ACTIONS_PER_TURN;
         self.world.train_armies(&current_player_index);
@@ -194,13 +471,47 @@ impl Game {
             }
         }
 
+        // Defeated players keep their index but no longer take turns
         self.turn += 1;
-        println!("Turn {}: {}", self.turn, self.current_player());
+        while self.current_player().is_defeated && self.players.iter().any(|p| !p.is_defeated) {
+            self.turn += 1;
+        }
+        if self.world.is_logged() {
+            println!("Turn {}: {}", self.turn, self.current_player());
+        }
+
+        // Armies cut off from their cities suffer at the start of the turn
+        let next_player_index = self.current_player_index();
+        self.world.apply_supply_attrition(next_player_index);
+        self.refresh_last_seen();
     }
+    /// Evaluates the human player's position with the AI, whenever it has changed since the last advice.
+    fn refresh_advice(&mut self) {
+        let player_index = self.current_player_index();
+        let situation = (self.turn, self.current_player().actions);
+        if !self.advisor || self.current_player().ai.is_some() || self.advice_for == Some(situation) {return}
+        let view = self.player_view(Some(player_index));
+        let mut advice = AI::new().generate_targets(&player_index, &view);
+        advice.truncate(ADVISOR_SUGGESTIONS);
+        drop(view);
+        self.advice = advice;
+        self.advice_for = Some(situation);
+    }
+
     pub fn _update(&mut self) {
         let current_player_index = self.current_player_index();
-        if self.players.len() <= 1 {
-            println!("{} wins!", self.current_player());
+        if !self.winners.is_empty() {return}
+        if let Some(winners) = self.check_victory() {
+            let names: Vec<String> = winners.iter().map(|&i| self.players[i].to_string()).collect();
+            if self.world.is_logged() {
+                println!("{} won!", names.join(" & "));
+            }
+            self.winners = winners;
+            return
+        }
+        if self.current_player().is_defeated {
+            self.next_turn();
+            return
         }
 
         // Force a player to skip a turn if he has no units to move or no action points left.
@@ -210,18 +521,81 @@ impl Game {
             return
         }
 
+        self.refresh_advice();
+
         // Let AI make a move
-        if let Some(ai) = &self.current_player().ai {
-            let targets = ai.generate_targets(&current_player_index, &self.world);
-            for target in targets {
-                if self.current_player().actions > 0 {
-                    self.click(&target.origin);
-                    self.click(&target.target);
-                } else {
-                    break
+        if self.current_player().ai.is_some() && self.ai_orders.is_none() {
+            self.plan_ai_turn();
+        }
+        if self.ai_orders.is_some() {
+            self.execute_ai_orders();
+        }
+    }
+
+    /// Lets the current player's bot make its diplomatic proposals, and queues its orders.
+    fn plan_ai_turn(&mut self) {
+        let current_player_index = self.current_player_index();
+        let proposals = diplomacy::ai_proposals(&self.player_view(Some(current_player_index)), &self.diplomacy, current_player_index, self.players.len());
+        for proposal in proposals {
+            self.propose(proposal.from, proposal.to, proposal.relation);
+        }
+        // The bot is taken out of the player while it plans, as it may need to change its own state
+        if let Some(mut bot) = self.current_player_mut().ai.take() {
+            let actions = self.current_player().actions;
+            let view = self.player_view(Some(current_player_index));
+            let orders = bot.plan_orders(current_player_index, &view, actions);
+            let explanation = match &bot {
+                Bot::Scorer(ai) if self.ai_debug => Some(ai.explain_turn(&current_player_index, &view, self.turn, &orders)),
+                _ => None,
+            };
+            drop(view);
+            self.current_player_mut().ai = Some(bot);
+            if let Some(explanation) = explanation {
+                dump_ai_explanation(&explanation);
+                self.ai_explanation = Some(explanation);
+            }
+            self.ai_orders = Some(orders.into());
+        }
+    }
+
+    /// Whether the current player has a movable army at the origin that can reach the target.
+    /// Bots may send anything, e.g. cubes off the map, which are dropped instead of clicked.
+    fn is_legal_order(&self, order: &Order) -> bool {
+        let player_index = self.current_player_index();
+        let is_own_army = self.world.get(&order.origin).is_some_and(|tile| {
+            tile.army.as_ref().is_some_and(|army| army.owner_index == Some(player_index) && army.can_move)
+        });
+        is_own_army && self.world.get_all_legal_moves(&order.origin, &player_index).contains(&order.target)
+    }
+
+    /// Executes the queued AI orders one per delay, so that humans can follow them.
+    /// Without a delay, or while fast-forwarding, the whole queue is executed at once.
+    fn execute_ai_orders(&mut self) {
+        let is_paced = self.ai_order_delay > 0. && !self.fast_forward;
+        loop {
+            if is_paced && date::now() - self.last_order_time < self.ai_order_delay {return}
+            let order = self.ai_orders.as_mut().and_then(|orders| orders.pop_front());
+            match order {
+                Some(order) if self.current_player().actions > 0 && !self.is_legal_order(&order) => {
+                    if self.world.is_logged() {
+                        println!("Dropped illegal order from {:?} to {:?}", order.origin, order.target);
+                    }
+                }
+                Some(order) if self.current_player().actions > 0 => {
+                    self.click(&order.origin);
+                    self.click(&order.target);
+                    self.last_order_time = date::now();
+                    if self.follow_ai {
+                        self.camera_target = Some(order.target);
+                    }
+                    if is_paced {return}
+                }
+                _ => {
+                    self.ai_orders = None;
+                    self.current_player_mut().skip_turn();
+                    return
                 }
             }
-            self.current_player_mut().skip_turn();
         }
     }
 }
@@ -0,0 +1,293 @@
+//! Imports real geography as a scenario. Land polygons, river polylines and populated places
+//! are read from shapefiles or GeoJSON in longitude/latitude, projected, laid over a hex grid
+//! and saved in the editor format, ready for the editor and for tournaments.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+use shapefile::dbase::FieldValue;
+use shapefile::PolygonRing;
+use shapefile::Shape;
+
+use crate::cubic;
+use crate::cubic::Cube;
+use crate::cubic::Layout;
+use crate::cubic::Pixel;
+use crate::map_editor::Editor;
+use crate::world::gen::is_inside_polygon;
+use crate::world::Locality;
+use crate::world::LocalityCategory;
+use crate::world::Tile;
+use crate::world::TileCategory;
+use crate::Player;
+use crate::World;
+
+// Projected maps are in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum Projection {
+    #[default]
+    Mercator,
+    // Plate carrée, true to scale along the middle latitude of the land.
+    Equirectangular,
+}
+
+impl Projection {
+    fn project(&self, [lon, lat]: [f64; 2], parallel: f64) -> (f32, f32) {
+        let (lon, lat) = (lon.to_radians(), lat.to_radians());
+        // y grows downwards, as on the screen
+        let (x, y) = match self {
+            Projection::Mercator => (lon, -(std::f64::consts::FRAC_PI_4 + lat / 2.).tan().ln()),
+            Projection::Equirectangular => (lon * parallel.to_radians().cos(), -lat),
+        };
+        ((x * EARTH_RADIUS_KM) as f32, (y * EARTH_RADIUS_KM) as f32)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportConfig {
+    // Polygon layer of the land, e.g. a coastline or administrative boundaries. Shapefile (.shp) or GeoJSON (.json, .geojson).
+    pub land: String,
+    // Polyline layer of the rivers.
+    #[serde(default)]
+    pub rivers: Option<String>,
+    // Point layer of the populated places.
+    #[serde(default)]
+    pub places: Option<String>,
+    // Attribute holding the name of a place.
+    #[serde(default = "default_name_field")]
+    pub name_field: String,
+    // Attribute holding the population of a place, so that the biggest places are placed first.
+    #[serde(default)]
+    pub population_field: Option<String>,
+    #[serde(default)]
+    pub projection: Projection,
+    // Width of the map in hexes, the hex size follows from the extent of the land.
+    pub width: i32,
+    #[serde(default)]
+    pub max_cities: Option<usize>,
+    // The biggest cities become the capitals of this many players.
+    #[serde(default)]
+    pub players: usize,
+    pub output: String,
+}
+
+fn default_name_field() -> String {
+    "name".to_string()
+}
+
+enum Geometry {
+    Points(Vec<[f64; 2]>),
+    Lines(Vec<Vec<[f64; 2]>>),
+    // Every polygon is its outer ring followed by its holes.
+    Polygons(Vec<Vec<Vec<[f64; 2]>>>),
+}
+
+struct Feature {
+    geometry: Geometry,
+    properties: HashMap<String, String>,
+}
+
+/// Reads the config, imports the layers and saves the scenario.
+pub fn run(config_path: &str) {
+    let f = File::open(config_path).expect("file should open read only");
+    let config: ImportConfig = serde_json::from_reader(f).expect("file should be proper JSON");
+    let editor = import(&config);
+    if let Some(directory) = Path::new(&config.output).parent() {
+        std::fs::create_dir_all(directory).expect("output directory should be creatable");
+    }
+    println!("Imported {} tiles, {} river sides and {} players", editor.world.len(), editor.world.rivers.len(), editor.players.len());
+    editor.to_json(&config.output);
+}
+
+pub fn import(config: &ImportConfig) -> Editor {
+    let polygons: Vec<Vec<Vec<[f64; 2]>>> = read_layer(&config.land).into_iter().flat_map(|feature| match feature.geometry {
+        Geometry::Polygons(polygons) => polygons,
+        _ => vec!(),
+    }).collect();
+    let latitudes = polygons.iter().flatten().flatten().map(|point| point[1]);
+    let (lat_min, lat_max) = latitudes.fold((f64::MAX, f64::MIN), |(min, max), lat| (min.min(lat), max.max(lat)));
+    assert!(lat_min <= lat_max, "{} should contain polygons", config.land);
+    let parallel = (lat_min + lat_max) / 2.;
+    let project = |point: &[f64; 2]| config.projection.project(*point, parallel);
+
+    let polygons: Vec<Vec<Vec<(f32, f32)>>> = polygons.iter().map(|rings| {
+        rings.iter().map(|ring| ring.iter().map(project).collect()).collect()
+    }).collect();
+    let points = polygons.iter().flatten().flatten();
+    let (x_min, x_max) = points.clone().fold((f32::MAX, f32::MIN), |(min, max), p| (min.min(p.0), max.max(p.0)));
+    let (y_min, y_max) = points.fold((f32::MAX, f32::MIN), |(min, max), p| (min.min(p.1), max.max(p.1)));
+
+    // Flat hexes are 1.5 sizes apart horizontally and sqrt(3) sizes vertically
+    let size = (x_max - x_min) / (1.5 * config.width.max(1) as f32);
+    let height = ((y_max - y_min) / (3f32.sqrt() * size)).ceil() as i32;
+    let layout = Layout{orientation: cubic::OrientationKind::Flat(cubic::FLAT), size: [size, size], origin: [x_min, y_min]};
+    let is_land = |pixel: Pixel<f32>| polygons.iter().any(|rings| {
+        is_inside_polygon(&rings[0], pixel) && !rings[1..].iter().any(|hole| is_inside_polygon(hole, pixel))
+    });
+
+    let mut world = World::new();
+    for q in 0..=config.width {
+        let q_offset = q >> 1;
+        for r in (-q_offset)..=(height - q_offset) {
+            let cube = Cube::new(q, r);
+            let category = match is_land(Cube::<f32>::from(cube).to_pixel(&layout)) {
+                true => TileCategory::Farmland,
+                false => TileCategory::Water,
+            };
+            world.insert(cube, Tile::new(category));
+        }
+    }
+
+    if let Some(path) = &config.rivers {
+        let lines: Vec<Vec<Pixel<f32>>> = read_layer(path).into_iter().flat_map(|feature| match feature.geometry {
+            Geometry::Lines(lines) => lines,
+            _ => vec!(),
+        }).map(|line| line.iter().map(|point| {
+            let (x, y) = project(point);
+            Pixel(x, y)
+        }).collect()).collect();
+        world.rivers = crate::river::trace_rivers(&lines, &layout);
+    }
+
+    let mut players = vec!();
+    if let Some(path) = &config.places {
+        let mut places: Vec<(String, f64, [f64; 2])> = read_layer(path).into_iter().filter_map(|feature| {
+            let name = feature.properties.get(&config.name_field)?.clone();
+            let population = config.population_field.as_ref()
+                .and_then(|field| feature.properties.get(field))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.);
+            match feature.geometry {
+                Geometry::Points(points) => points.first().map(|point| (name, population, *point)),
+                _ => None,
+            }
+        }).collect();
+        places.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut placed = 0;
+        for (name, _, point) in places {
+            if config.max_cities.is_some_and(|max| placed >= max) {break}
+            let (x, y) = project(&point);
+            let cube: Cube<i32> = cubic::pixel_to_cube(&layout, [x, y]).round();
+            let is_free_land = world.get(&cube).is_some_and(|tile| matches!(tile.category, TileCategory::Farmland))
+                && cube.disc(1).iter().all(|n| world.get(n).map_or(true, |tile| tile.locality.is_none()));
+            if !is_free_land {continue}
+
+            let is_coastal = cube.disc(1).iter().any(|n| world.get(n).is_some_and(|tile| matches!(tile.category, TileCategory::Water)));
+            let mut tile = world.remove(&cube).unwrap();
+            // The biggest cities come first, so they are the capitals
+            tile.locality = Some(match (players.len() < config.players, is_coastal) {
+                (true, _) => {
+                    tile.owner_index = Some(players.len());
+                    let mut player = Player::new(&name, None);
+                    player.capital_pos = Some(cube);
+                    players.push(player);
+                    Locality::new(&name, LocalityCategory::Capital)
+                }
+                (false, true) => Locality::new(&name, LocalityCategory::PortCity),
+                (false, false) => Locality::new(&name, LocalityCategory::City),
+            });
+            world.insert(cube, tile);
+            placed += 1;
+        }
+    }
+    if players.len() < config.players {
+        println!("Only {} of {} capitals could be placed.", players.len(), config.players);
+    }
+    Editor::new(world, players)
+}
+
+fn read_layer(path: &str) -> Vec<Feature> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("shp") => read_shapefile(path),
+        Some("json") | Some("geojson") => read_geojson(path),
+        _ => panic!("{} should be a shapefile (.shp) or GeoJSON (.json, .geojson)", path),
+    }
+}
+
+fn read_shapefile(path: &str) -> Vec<Feature> {
+    let mut reader = shapefile::Reader::from_path(path).expect("shapefile should open read only");
+    reader.iter_shapes_and_records().filter_map(|result| {
+        let (shape, record) = result.expect("shapefile should be valid");
+        let geometry = match shape {
+            Shape::Point(p) => Geometry::Points(vec![[p.x, p.y]]),
+            Shape::Multipoint(multipoint) => Geometry::Points(multipoint.points().iter().map(|p| [p.x, p.y]).collect()),
+            Shape::Polyline(polyline) => Geometry::Lines(polyline.parts().iter().map(|part| part.iter().map(|p| [p.x, p.y]).collect()).collect()),
+            Shape::Polygon(polygon) => {
+                // Every outer ring starts a polygon, the inner rings after it are its holes
+                let mut polygons: Vec<Vec<Vec<[f64; 2]>>> = vec!();
+                for ring in polygon.rings() {
+                    let points = ring.points().iter().map(|p| [p.x, p.y]).collect();
+                    match ring {
+                        PolygonRing::Outer(_) => polygons.push(vec![points]),
+                        PolygonRing::Inner(_) => if let Some(rings) = polygons.last_mut() {rings.push(points)},
+                    }
+                }
+                Geometry::Polygons(polygons)
+            }
+            _ => {
+                println!("Unsupported shape type in {}", path);
+                return None
+            }
+        };
+        let properties = record.into_iter().filter_map(|(field, value)| {
+            let value = match value {
+                FieldValue::Character(Some(text)) | FieldValue::Memo(text) => text,
+                FieldValue::Numeric(Some(number)) | FieldValue::Double(number) => number.to_string(),
+                FieldValue::Float(Some(number)) => number.to_string(),
+                FieldValue::Integer(number) => number.to_string(),
+                _ => return None,
+            };
+            Some((field, value))
+        }).collect();
+        Some(Feature{geometry, properties})
+    }).collect()
+}
+
+fn read_geojson(path: &str) -> Vec<Feature> {
+    let f = File::open(path).expect("file should open read only");
+    let json: Value = serde_json::from_reader(f).expect("file should be proper JSON");
+    json["features"].as_array().expect("GeoJSON should be a FeatureCollection").iter().filter_map(|feature| {
+        let geometry = parse_geometry(&feature["geometry"])?;
+        let properties = feature["properties"].as_object().map(|properties| {
+            properties.iter().map(|(key, value)| {
+                let value = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                (key.clone(), value)
+            }).collect()
+        }).unwrap_or_default();
+        Some(Feature{geometry, properties})
+    }).collect()
+}
+
+fn position(value: &Value) -> [f64; 2] {
+    [value[0].as_f64().unwrap_or(0.), value[1].as_f64().unwrap_or(0.)]
+}
+
+fn positions(value: &Value) -> Vec<[f64; 2]> {
+    value.as_array().map_or(vec!(), |array| array.iter().map(position).collect())
+}
+
+fn rings(value: &Value) -> Vec<Vec<[f64; 2]>> {
+    value.as_array().map_or(vec!(), |array| array.iter().map(positions).collect())
+}
+
+fn parse_geometry(geometry: &Value) -> Option<Geometry> {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str()? {
+        "Point" => Some(Geometry::Points(vec![position(coordinates)])),
+        "MultiPoint" => Some(Geometry::Points(positions(coordinates))),
+        "LineString" => Some(Geometry::Lines(vec![positions(coordinates)])),
+        "MultiLineString" => Some(Geometry::Lines(rings(coordinates))),
+        "Polygon" => Some(Geometry::Polygons(vec![rings(coordinates)])),
+        "MultiPolygon" => Some(Geometry::Polygons(coordinates.as_array()?.iter().map(rings).collect())),
+        _ => None,
+    }
+}
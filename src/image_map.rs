@@ -0,0 +1,186 @@
+//! Builds a scenario from an image, e.g. a painted sketch, a palette map or a grayscale heightmap.
+//! Every pixel votes for the hex under it, and a legend maps the colours or grey levels
+//! to tiles, localities and owners. The scenario is saved in the editor format.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use macroquad::texture::Image;
+use serde::Deserialize;
+
+use crate::cubic;
+use crate::cubic::Cube;
+use crate::cubic::Layout;
+use crate::map_editor::Editor;
+use crate::world::Locality;
+use crate::world::LocalityCategory;
+use crate::world::Tile;
+use crate::world::TileCategory;
+use crate::Player;
+use crate::World;
+
+// Colours further apart than this fall back to the grey levels of the legend.
+const COLOR_TOLERANCE: f32 = 48.;
+// Share of a hex an entry with a locality must cover for the locality to be placed, so that small dots count.
+const MARKER_SHARE: f32 = 0.1;
+// Hexes cut by the border of the image are kept if at least this share of them is covered.
+const MIN_COVERAGE: f32 = 0.5;
+
+/// One line of the legend. Either a colour, matched to the nearest pixels,
+/// or the highest grey level of a band, for heightmaps.
+#[derive(Deserialize)]
+pub struct LegendEntry {
+    // "#rrggbb"
+    #[serde(default)]
+    pub color: Option<String>,
+    // 0 to 255, pixels take the first band at or above their level.
+    #[serde(default)]
+    pub max_level: Option<u8>,
+    pub tile: TileCategory,
+    #[serde(default)]
+    pub locality: Option<LocalityCategory>,
+    #[serde(default)]
+    pub owner: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ImageImportConfig {
+    // PNG, grayscale, palette or RGB. Transparent pixels are left out of the map.
+    pub image: String,
+    pub legend: Vec<LegendEntry>,
+    // Width of the map in hexes, the hex size follows from the width of the image.
+    pub width: i32,
+    pub output: String,
+}
+
+/// Reads the config, samples the image and saves the scenario.
+pub fn run(config_path: &str) {
+    let f = File::open(config_path).expect("file should open read only");
+    let config: ImageImportConfig = serde_json::from_reader(f).expect("file should be proper JSON");
+    let bytes = std::fs::read(&config.image).expect("image should open read only");
+    let image = Image::from_file_with_format(&bytes, None);
+    let editor = import(&config, &image);
+    if let Some(directory) = Path::new(&config.output).parent() {
+        std::fs::create_dir_all(directory).expect("output directory should be creatable");
+    }
+    println!("Imported {} tiles and {} players", editor.world.len(), editor.players.len());
+    editor.to_json(&config.output);
+}
+
+fn parse_color(color: &str) -> [u8; 3] {
+    let hex = color.trim_start_matches('#');
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => [r, g, b],
+        _ => panic!("legend colour {} should be #rrggbb", color),
+    }
+}
+
+struct Legend {
+    colors: Vec<(usize, [u8; 3])>,
+    // Sorted by their max_level.
+    levels: Vec<(usize, u8)>,
+}
+
+impl Legend {
+    fn new(entries: &[LegendEntry]) -> Self {
+        let colors = entries.iter().enumerate().filter_map(|(i, entry)| Some((i, parse_color(entry.color.as_ref()?)))).collect();
+        let mut levels: Vec<(usize, u8)> = entries.iter().enumerate().filter_map(|(i, entry)| Some((i, entry.max_level?))).collect();
+        levels.sort_by_key(|(_, level)| *level);
+        Legend{colors, levels}
+    }
+
+    /// The legend entry of a pixel: a close colour first, then the grey level, then the nearest colour.
+    fn classify(&self, [r, g, b, a]: [u8; 4]) -> Option<usize> {
+        if a < 128 {return None}
+        let distance = |color: &[u8; 3]| {
+            let d = [r as f32 - color[0] as f32, g as f32 - color[1] as f32, b as f32 - color[2] as f32];
+            (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+        };
+        let nearest = self.colors.iter().map(|(i, color)| (*i, distance(color))).min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, _)) = nearest.filter(|(_, d)| *d <= COLOR_TOLERANCE) {
+            return Some(i)
+        }
+        let level = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+        if let Some((i, _)) = self.levels.iter().find(|(_, max_level)| level <= *max_level) {
+            return Some(*i)
+        }
+        nearest.map(|(i, _)| i)
+    }
+}
+
+pub fn import(config: &ImageImportConfig, image: &Image) -> Editor {
+    let legend = Legend::new(&config.legend);
+    // Flat hexes are 1.5 sizes apart horizontally
+    let size = image.width() as f32 / (1.5 * config.width.max(1) as f32);
+    let layout = Layout{orientation: cubic::OrientationKind::Flat(cubic::FLAT), size: [size, size], origin: [0., 0.]};
+
+    // Votes of the pixels for every legend entry, the last count is for pixels matching none
+    let mut votes: HashMap<Cube<i32>, Vec<usize>> = HashMap::new();
+    let data = image.get_image_data();
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let cube: Cube<i32> = cubic::pixel_to_cube(&layout, [x as f32 + 0.5, y as f32 + 0.5]).round();
+            let counts = votes.entry(cube).or_insert_with(|| vec![0; config.legend.len() + 1]);
+            let entry = legend.classify(data[y * image.width() + x]).unwrap_or(config.legend.len());
+            counts[entry] += 1;
+        }
+    }
+
+    let hex_area = 1.5 * 3f32.sqrt() * size * size;
+    let mut cubes: Vec<&Cube<i32>> = votes.keys().collect();
+    cubes.sort();
+    let mut world = World::new();
+    // Marked hexes with their marker and its votes
+    let mut markers: Vec<(Cube<i32>, usize, usize)> = vec!();
+    for cube in cubes {
+        let counts = &votes[cube];
+        let total: usize = counts.iter().sum();
+        let (base, base_votes) = counts[..config.legend.len()].iter().enumerate().max_by_key(|(i, count)| (**count, std::cmp::Reverse(*i))).unwrap_or((0, &0));
+        if (total as f32) < MIN_COVERAGE * hex_area || *base_votes < counts[config.legend.len()] {continue}
+
+        let base = &config.legend[base];
+        let mut tile = Tile::new(base.tile.clone());
+        tile.owner_index = base.owner;
+        let marker = config.legend.iter().enumerate()
+            .filter(|(i, entry)| entry.locality.is_some() && counts[*i] as f32 >= MARKER_SHARE * total as f32)
+            .max_by_key(|(i, _)| (counts[*i], std::cmp::Reverse(*i)));
+        if let Some((i, _)) = marker {
+            markers.push((*cube, i, counts[i]));
+        }
+        world.insert(*cube, tile);
+    }
+
+    // A marker spread over several hexes is placed once, in the hex it covers the most
+    markers.sort_by_key(|(cube, _, count)| (std::cmp::Reverse(*count), *cube));
+    let mut capitals: Vec<(Cube<i32>, Option<usize>)> = vec!();
+    let mut names = crate::load_locality_names().into_iter();
+    for (cube, i, _) in markers {
+        if cube.disc(1).iter().any(|n| world.get(n).is_some_and(|tile| tile.locality.is_some())) {continue}
+        let entry = &config.legend[i];
+        let mut tile = world.remove(&cube).unwrap();
+        tile.category = entry.tile.clone();
+        tile.owner_index = entry.owner.or(tile.owner_index);
+        let category = entry.locality.clone().unwrap();
+        if matches!(category, LocalityCategory::Capital) {
+            capitals.push((cube, tile.owner_index));
+        }
+        tile.locality = Some(Locality::new(&names.next().unwrap_or_default(), category));
+        world.insert(cube, tile);
+    }
+
+    // Every owner is a player, capitals drawn without an owner get players of their own
+    let mut player_count = world.values().filter_map(|tile| tile.owner_index).max().map_or(0, |max| max + 1);
+    for (cube, owner) in capitals.iter_mut().filter(|(_, owner)| owner.is_none()) {
+        *owner = Some(player_count);
+        world.set_tile_owner(cube, player_count);
+        player_count += 1;
+    }
+    let players = (0..player_count).map(|index| {
+        let mut player = Player::new("", None);
+        player.capital_pos = capitals.iter().find(|(_, owner)| *owner == Some(index)).map(|(cube, _)| *cube);
+        player
+    }).collect();
+    Editor::new(world, players)
+}
@@ -3,7 +3,9 @@ use crate::cubic::OrientationKind;
 use crate::game::Game;
 use crate::Layout;
 use crate::cubic;
+use crate::diplomacy::Relation;
 use crate::map_editor::Editor;
+use crate::setup::Setup;
 use crate::mquad::Assets;
 use crate::world::LocalityCategory;
 use crate::world::Tile;
@@ -13,6 +15,8 @@ use macroquad::prelude::*;
 
 const PAN_SPEED: f32 = 8.;
 const ZOOM_SPEED: f32 = 1.;
+// Share of the remaining distance the camera covers every frame when following the AI.
+const FOLLOW_SPEED: f32 = 0.15;
 
 // fn swtich() -> bool {
 //     if is_key_pressed(KeyCode::F1) {
@@ -21,6 +25,24 @@ const ZOOM_SPEED: f32 = 1.;
 // }
 
 fn poll_camera_inputs(layout: &mut Layout<f32>) {
+    poll_mouse_camera_inputs(layout);
+
+    // KEY PAN
+    if is_key_down(KeyCode::Right) {
+        layout.origin[0] -= PAN_SPEED;
+    }
+    if is_key_down(KeyCode::Left) {
+        layout.origin[0] += PAN_SPEED;
+    }
+    if is_key_down(KeyCode::Up) {
+        layout.origin[1] += PAN_SPEED;
+    }
+    if is_key_down(KeyCode::Down) {
+        layout.origin[1] -= PAN_SPEED;
+    }
+}
+
+fn poll_mouse_camera_inputs(layout: &mut Layout<f32>) {
     // WHEEL ZOOM
     let (_, mouse_wheel_y) = mouse_wheel();
     if mouse_wheel_y > 0. {
@@ -47,20 +69,6 @@ fn poll_camera_inputs(layout: &mut Layout<f32>) {
     if pos_y == screen_height() - 1. {
         layout.origin[1] -= PAN_SPEED;
     }
-
-    // KEY PAN
-    if is_key_down(KeyCode::Right) {
-        layout.origin[0] -= PAN_SPEED;
-    }
-    if is_key_down(KeyCode::Left) {
-        layout.origin[0] += PAN_SPEED;
-    }
-    if is_key_down(KeyCode::Up) {
-        layout.origin[1] += PAN_SPEED;
-    }
-    if is_key_down(KeyCode::Down) {
-        layout.origin[1] -= PAN_SPEED;
-    }
 }
 
 pub fn poll_map_editor_inputs(editor: &mut Editor, layout: &mut Layout<f32>) -> bool {
@@ -78,6 +86,12 @@ pub fn poll_map_editor_inputs(editor: &mut Editor, layout: &mut Layout<f32>) ->
         editor.toggle_layer();
     }
 
+    if is_key_pressed(KeyCode::T) {
+        let pos = mouse_position().into();
+        let cube = cubic::pixel_to_cube(layout, pos).round::<i32>();
+        editor.cycle_team(&cube);
+    }
+
     if is_key_down(KeyCode::F5) {
         std::fs::create_dir_all("assets/scenarios");
         editor.to_json("assets/scenarios/quicksave.json");
@@ -105,6 +119,33 @@ pub fn poll_map_editor_inputs(editor: &mut Editor, layout: &mut Layout<f32>) ->
     exit
 }
 
+// Up and down pick a setting, left and right change it, typing edits names and the seed.
+// Enter starts the game, see run() in main.rs. The arrows are taken, so the camera follows the mouse only.
+pub fn poll_setup_inputs(setup: &mut Setup, layout: &mut Layout<f32>) -> bool {
+    if is_key_pressed(KeyCode::Up) {
+        setup.select(-1);
+    }
+    if is_key_pressed(KeyCode::Down) {
+        setup.select(1);
+    }
+    if is_key_pressed(KeyCode::Left) {
+        setup.change(-1);
+    }
+    if is_key_pressed(KeyCode::Right) {
+        setup.change(1);
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        setup.backspace();
+    }
+    while let Some(character) = get_char_pressed() {
+        setup.type_char(character);
+    }
+
+    poll_mouse_camera_inputs(layout);
+
+    is_key_pressed(KeyCode::Escape)
+}
+
 pub fn poll_inputs(game: &mut Game, layout: &mut Layout<f32>) -> bool {
     // if is_key_down() {
     //     let key = last_key_pressed();
@@ -127,7 +168,22 @@ pub fn poll_inputs(game: &mut Game, layout: &mut Layout<f32>) -> bool {
         player.skip_turn();
     }
 
+    if game.players[player_index].ai.is_none() {
+        if is_key_pressed(KeyCode::H) {
+            game.advisor = !game.advisor;
+        }
+        poll_diplomacy_inputs(game, layout);
+        if is_key_down(KeyCode::LeftControl) && is_key_pressed(KeyCode::R) {
+            game.resign();
+        }
+    }
+
     poll_camera_inputs(layout);
+    poll_ai_pacing_inputs(game, layout);
+
+    if is_key_pressed(KeyCode::F3) {
+        game.ai_debug = !game.ai_debug;
+    }
 
     if is_key_down(KeyCode::F5) {
         //save_map(&game.world.world, "assets/saves/quicksave.json");
@@ -146,6 +202,54 @@ pub fn poll_inputs(game: &mut Game, layout: &mut Layout<f32>) -> bool {
     exit
 }
 
+// Holding F fast-forwards the AI, V toggles the camera following its orders.
+fn poll_ai_pacing_inputs(game: &mut Game, layout: &mut Layout<f32>) {
+    game.fast_forward = is_key_down(KeyCode::F);
+    if is_key_pressed(KeyCode::V) {
+        game.follow_ai = !game.follow_ai;
+        game.camera_target = None;
+    }
+
+    // Pan smoothly until the target is centered on the screen
+    if let Some(target) = game.camera_target {
+        let p = Cube::<f32>::from(target).to_pixel(layout);
+        let dx = screen_width() / 2. - p.0;
+        let dy = screen_height() / 2. - p.1;
+        if dx.abs() < 1. && dy.abs() < 1. {
+            game.camera_target = None;
+        } else {
+            layout.origin[0] += dx * FOLLOW_SPEED;
+            layout.origin[1] += dy * FOLLOW_SPEED;
+        }
+    }
+}
+
+// Diplomatic actions target the owner of the tile under the cursor.
+fn poll_diplomacy_inputs(game: &mut Game, layout: &Layout<f32>) {
+    let player_index = game.current_player_index();
+    let pos = mouse_position().into();
+    let cube = cubic::pixel_to_cube(layout, pos).round::<i32>();
+    let relation = match () {
+        _ if is_key_pressed(KeyCode::A) => Some(Relation::Alliance),
+        _ if is_key_pressed(KeyCode::N) => Some(Relation::NonAggression),
+        _ if is_key_pressed(KeyCode::W) => Some(Relation::War),
+        _ => None,
+    };
+    if let Some(relation) = relation {
+        // The owner as known to the player, so that hidden tiles do not give away who holds them
+        let hovered_owner = game.player_view(Some(player_index)).get(&cube).and_then(|tile| tile.owner_index);
+        if let Some(owner) = hovered_owner.filter(|&owner| owner != player_index) {
+            game.propose(player_index, owner, relation);
+        }
+    }
+    if is_key_pressed(KeyCode::Y) {
+        game.answer_proposal(true);
+    }
+    if is_key_pressed(KeyCode::X) {
+        game.answer_proposal(false);
+    }
+}
+
 pub fn draw_tile_selector(&layout: &Layout<f32>) {
     let vertical = match layout.orientation {
         OrientationKind::Pointy(_) => true,
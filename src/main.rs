@@ -4,26 +4,37 @@ mod cubic;
 mod game;
 mod world;
 mod ai;
+mod agent;
+mod diplomacy;
+mod rng;
+mod tournament;
+mod tuning;
 // mod pixels;
 mod mquad;
 mod inputs;
 mod map_editor;
 mod river;
 mod shapefiles;
+mod gis;
+mod image_map;
+mod setup;
 
-use ai::*;
 use cubic::*;
 use game::*;
 use world::*;
 use inputs::*;
 use map_editor::*;
-use std::{fs::File, f32::consts::PI};
+use setup::*;
 // use crate::pixels::*;
 use mquad::*;
 use macroquad::{miniquad::fs::load_file, prelude::*};
 
 const WATER_FRAGMENT_SHADER: &'static str = include_str!("../assets/water_fragment_shader.glsl");
 const WATER_VERTEX_SHADER: &'static str = include_str!("../assets/water_vertex_shader.glsl");
+// Outline of the custom shape on the settings screen, and how many of its vertices are kept.
+// Outline of the Custom shape, unless `--shape` gives another polygon shapefile.
+const DEFAULT_SHAPE: &str = "assets/ua_shp/ukr_admbnda_adm0_sspe_20230201.shp";
+const MAX_SHAPE_VERTICES: usize = 2000;
 
 pub trait Component {
     fn poll(&mut self, layout: &mut Layout<f32>) -> bool;
@@ -32,54 +43,49 @@ pub trait Component {
     // fn swap(self) -> dyn Component;
 }
 
-fn load_rivers(shape: &Vec<(f32, f32)>) -> Vec<(usize, f32, f32)> {
-    let x_min = shape.iter().fold(f32::NAN, |a, &b| a.min(b.0));
-    let y_min = shape.iter().fold(f32::NAN, |a, &b| a.min(b.1));
-    // shape = shape.iter().map(|(x, y)| (x - x_min, y - y_min)).collect();
-
-    let mut river: Vec<(usize, f32, f32)> = Vec::new();
-    let file = File::open("assets/shapes/ua-rivers.csv").unwrap();
-    let mut rdr = csv::Reader::from_reader(file);
-    // Iterate over each record in the CSV and parse the values
-    for (idx, result) in rdr.records().enumerate() {
-        let record = result.unwrap();
-        let first_value: f32 = record.get(23).unwrap().parse().unwrap();
-        let second_value: f32 = record.get(24).unwrap().parse().unwrap();
-        let id: i32 = record.get(0).unwrap().parse().unwrap();
-        let id: usize = id.abs() as usize;
-        // match record.get(12).unwrap().parse() {
-        //     Ok(_) => {},
-        //     Err(e) => print!("{:}", e),
-        // }
-        // let vertex_part: i32 = record.get(12).unwrap().parse().unwrap();
-        // let vertex_part_ring: i32 = record.get(13).unwrap().parse().unwrap();
-
-
-
-        let r = 6371000.0 / 750.; //1:250 is nearly max
-        let y = r * ((std::f32::consts::PI/4.) + (second_value.to_radians()/2.)).tan().ln();
-        let x = r * first_value.to_radians();
-        let val = (id, x - x_min, (y * -1.) - y_min);
-        // if id == 4029011 {river.push((id, x, y * -1.));}
-        if id == 25582 {river.push(val);}
-        // river.push(val);
-
-
-
-        // if vertex_part == 158 && vertex_part_ring == 0 {
-        //     // shape.push((first_value * r, second_value*(-1.) * r));
-        //     shape.push((x, y * -1.));
-        // }
-        //  if idx > 50000 {break}
-    }
-    river
+fn load_locality_names() -> Vec<String> {
+    // let f = File::open("assets/cities.json").expect("file should open read only");
+    let f = include_bytes!("../assets/cities.json");
+    let json: serde_json::Value = serde_json::from_reader(&f[..]).expect("file should be proper JSON");
+    json["data"].as_array().unwrap().iter().map(|el| el["asciiname"].to_string().replace("\"", "")).collect()
 }
 
-async fn load_assets() -> Assets {
-    // let f = File::open("assets/cities.json").expect("file should open read only");
+/// Cities with a "latitude" and "longitude" in degrees, for LocalitiesGen::Real. Others are left out.
+fn load_real_cities() -> Vec<world::gen::RealCity> {
     let f = include_bytes!("../assets/cities.json");
     let json: serde_json::Value = serde_json::from_reader(&f[..]).expect("file should be proper JSON");
-    let locality_names: Vec<_> = json["data"].as_array().unwrap().iter().map(|el| el["asciiname"].to_string().replace("\"", "")).collect();
+    json["data"].as_array().unwrap().iter().filter_map(|el| {
+        let (latitude, longitude) = (el["latitude"].as_f64()?, el["longitude"].as_f64()?);
+        Some(world::gen::RealCity{
+            name: el["asciiname"].as_str()?.to_string(),
+            population: el["population"].as_u64().unwrap_or(0),
+            position: world::gen::mercator(longitude as f32, latitude as f32),
+        })
+    }).collect()
+}
+
+/// The largest ring of a polygon shapefile in longitude/latitude, projected like the real cities, for ShapeGen::Custom.
+/// Thinned out, as every tile of the map is tested against every vertex.
+fn load_shape(path: &str) -> Vec<(f32, f32)> {
+    let mut reader = match shapefile::Reader::from_path(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            println!("No custom shape, {} failed to open: {}", path, e);
+            return vec!()
+        }
+    };
+    let rings = reader.iter_shapes_and_records().flat_map(|result| match result.expect("shapefile should be valid").0 {
+        shapefile::Shape::Polygon(polygon) => polygon.rings().iter().map(|ring| ring.points().to_vec()).collect(),
+        _ => vec!(),
+    });
+    let ring = rings.max_by_key(|ring| ring.len()).unwrap_or_default();
+    let step = ring.len().div_ceil(MAX_SHAPE_VERTICES).max(1);
+    ring.iter().step_by(step).map(|point| world::gen::mercator(point.x as f32, point.y as f32)).collect()
+}
+
+async fn load_assets(shape_path: &str) -> Assets {
+    let locality_names = load_locality_names();
+    let cities = load_real_cities();
     // let locality_names = locality_names_v.iter().map(String::as_str).collect();
     // let locality_names: Vec<&str> = locality_names_v.iter().map(|s| &**s).collect();
 
@@ -138,7 +144,7 @@ async fn load_assets() -> Assets {
     // let mut rdr = csv::Reader::from_reader(file);
 
     // Create a Vec<(f32, f32)> to store the data
-    let mut shape: Vec<(f32, f32)> = Vec::new();
+    let shape = load_shape(shape_path);
 
     // Iterate over each record in the CSV and parse the values
     // for (idx, result) in rdr.records().enumerate() {
@@ -161,9 +167,6 @@ async fn load_assets() -> Assets {
     //     //  if idx > 50000 {break}
     // }
 
-    // let river = load_rivers(&shape);
-    let river = vec![];
-
     //println!("{:?}", shape);
     //let shape = vec!((0.,0.), (500., -950.), (1000., 0.), (1000.,-1000.), (500., -950.), (0.,-1000.));
     // let shape = vec!((0.,0.), (1000., 0.), (1000.,-1000.), (0.,-1000.));
@@ -171,7 +174,7 @@ async fn load_assets() -> Assets {
     // let min_x = shape.iter().fold(0., |init: f32, (x, y)| init.min(*x));
     // let min_y = shape.iter().fold(0., |init: f32, (x, y)| init.min(*y));
 
-    Assets{locality_names, font, army, port, airport, fields, water_material, init_layout, shape, river}
+    Assets{locality_names, cities, font, army, port, airport, fields, water_material, init_layout, shape}
 }
 
 fn window_conf() -> Conf {
@@ -182,39 +185,6 @@ fn window_conf() -> Conf {
     }
 }
 
-fn new_game(assets: &mut Assets) -> Game {
-    let ai1 = AI{scores: DEFAULT_SCORES};
-    let ai2 = AI{scores: DEFAULT_SCORES};
-    let ai3 = AI{scores: DEFAULT_SCORES};
-    let ai4 = AI{scores: DEFAULT_SCORES};
-
-    // let player1 = Player::new("Redosia", Some(ai1));
-    let player1 = Player::new("Redosia", None);
-    // let player2 = Player::new("Bluekraine", Some(ai2)); // Umberaine?
-    let player2 = Player::new("Bluegaria", Some(ai2));
-    let player3 = Player::new("Greenland", Some(ai3));
-    let player4 = Player::new("Violetnam", Some(ai4));
-
-    let players = vec![player1, player2, player3, player4];
-    // let players = vec![player1, player2, ];//player3, player4];
-
-    let world = World::new();
-
-    // save_map(&game.world.world);
-    // let mut world = World::from_json("assets/maps/map.json");
-    // let mut world = World::from_json("assets/saves/quicksave.json");
-
-    let mut game = Game {
-        turn: 1,
-        players,
-        world,
-        victory_condition: game::VictoryCondition::Territory(0.30)
-    };
-
-    game.init_world(assets);
-    game
-}
-
 async fn game_loop(game: &mut Game, layout: &mut Layout<f32>, assets: &Assets) {
     let mut is_yet_won = false;
 
@@ -233,7 +203,7 @@ async fn game_loop(game: &mut Game, layout: &mut Layout<f32>, assets: &Assets) {
     
         game.update();
 
-        is_yet_won = game.victory_condition.check(&game.world, game.current_player_index());
+        is_yet_won = game.victory_condition.check(&game.world, &[game.current_player_index()]);
 
         next_frame().await;
         time += get_frame_time();
@@ -248,25 +218,56 @@ async fn game_loop(game: &mut Game, layout: &mut Layout<f32>, assets: &Assets) {
 // }
 
 enum State {
+    Setup,
     Game,
     Editor,
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
+    // `cubic --tournament config.json` and `cubic --tune config.json` play AI games without opening a window,
+    // `cubic --import config.json` turns GIS layers into a scenario, `cubic --import-image config.json` an image,
+    // `cubic --shape outline.shp` plays the Custom shape on another outline
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--tournament") {
+        let path = args.get(index + 1).expect("--tournament expects the path to a config file");
+        tournament::run(path);
+        return
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--tune") {
+        let path = args.get(index + 1).expect("--tune expects the path to a config file");
+        tuning::run(path);
+        return
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--import") {
+        let path = args.get(index + 1).expect("--import expects the path to a config file");
+        gis::run(path);
+        return
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--import-image") {
+        let path = args.get(index + 1).expect("--import-image expects the path to a config file");
+        image_map::run(path);
+        return
+    }
+    let shape_path = match args.iter().position(|arg| arg == "--shape") {
+        Some(index) => args.get(index + 1).expect("--shape expects the path to a polygon shapefile").clone(),
+        None => DEFAULT_SHAPE.to_string(),
+    };
+    macroquad::Window::from_config(window_conf(), run(shape_path));
+}
+
+async fn run(shape_path: String) {
     set_pc_assets_folder("assets");
-    let mut assets = load_assets().await;
+    let mut assets = load_assets(&shape_path).await;
 
     // run_editor(&assets).await;
 
-    let mut game = new_game(&mut assets);
+    // Players, their AIs and the world are chosen on the settings screen, see setup.rs
+    let mut setup = Setup::new(&assets);
+    let mut game = Game::new(vec!(), World::new(), VictoryCondition::Elimination);
     let mut editor = Editor::new(World::new(), vec!());
 
-    // let river = crate::river::generate_river(game.world.keys().collect());
-    // println!("river: {:?}", river);
-
-    let mut state = State::Game;
-    let mut app: &mut dyn Component = &mut game;
+    let mut state = State::Setup;
+    let mut app: &mut dyn Component = &mut setup;
 
 
     // let app: &mut dyn Component = &mut match state {
@@ -285,11 +286,17 @@ async fn main() {
         exit = app.poll(&mut layout);
         next_frame().await;
         app.update();
+        if matches!(state, State::Setup) && is_key_pressed(KeyCode::Enter) {
+            game = setup.new_game(&assets);
+            app = &mut game;
+            state = State::Game;
+        }
         if is_key_pressed(KeyCode::F1) {
             match state {
+                State::Setup => (),
                 State::Game => {
                     editor = game.into();
-                    game = Game{turn: 0, players: vec!(), world: World::new(), victory_condition: VictoryCondition::Elimination};
+                    game = Game::new(vec!(), World::new(), VictoryCondition::Elimination);
                     app = &mut editor;
                     state = State::Editor;
                 }
@@ -12,11 +12,55 @@ use crate::{cubic::Cube, world::{Tile, World, LocalityCategory}};
 
 #[derive(Serialize, Deserialize)]
 pub struct Editor {
+    #[serde(with = "scenario_world")]
     pub world: World,
     pub players: Vec<Player>,
     pub brush: Brush,
 }
 
+/// Scenarios save the rivers along with the tiles: `{"tiles": {"q_r": tile, ...}, "rivers": ["q_r", ...]}`.
+/// Older scenarios, saved as a bare map of tiles, still load.
+mod scenario_world {
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serialize;
+    use serde::Serializer;
+
+    use crate::river::CubeSide;
+    use crate::world::World;
+
+    #[derive(Serialize)]
+    struct SavedWorldRef<'a> {
+        tiles: &'a World,
+        rivers: Vec<&'a CubeSide>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SavedWorld {
+        WithRivers {
+            tiles: World,
+            #[serde(default)]
+            rivers: Vec<CubeSide>,
+        },
+        Tiles(World),
+    }
+
+    pub fn serialize<S: Serializer>(world: &World, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedWorldRef{tiles: world, rivers: world.rivers.iter().collect()}.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<World, D::Error> {
+        Ok(match SavedWorld::deserialize(deserializer)? {
+            SavedWorld::WithRivers{mut tiles, rivers} => {
+                tiles.rivers = rivers.into_iter().collect();
+                tiles
+            }
+            SavedWorld::Tiles(world) => world,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Brush {
     idx: usize,
@@ -84,7 +128,9 @@ impl PlaceItem for LocalityCategory {
             // tile.locality = Some(self.clone().into());
             if matches!(self, LocalityCategory::Capital) {
                 let player_count = editor.players.iter().len();
-                editor.players.push(Player{name: "".to_string(), actions: 5, ai: None, selection: None, capital_pos: Some(*cube)});
+                let mut player = Player::new("", None);
+                player.capital_pos = Some(*cube);
+                editor.players.push(player);
                 tile.owner_index = Some(player_count);
             }
         }
@@ -156,6 +202,19 @@ impl Editor {
         };
         self.brush.idx %= max + 1;
     }
+    /// Moves the owner of the tile to the next team, or out of any team after the last one.
+    pub fn cycle_team(&mut self, cube: &Cube<i32>) {
+        let player_count = self.players.len();
+        if let Some(index) = self.world.get(cube).and_then(|tile| tile.owner_index) {
+            let player = &mut self.players[index];
+            player.team = match player.team {
+                None => Some(0),
+                Some(team) if team + 1 < player_count => Some(team + 1),
+                Some(_) => None,
+            };
+            println!("{} team: {:?}", index, player.team);
+        }
+    }
     pub fn toggle_layer(&mut self) {
         let mut b = BrushLayer::iter();
         let max = b.len();
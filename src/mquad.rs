@@ -3,6 +3,7 @@ use std::f32::consts::PI;
 use macroquad::prelude::*;
 use macroquad::texture::load_image;
 
+use crate::ai::TurnExplanation;
 use crate::cubic;
 use crate::World;
 use crate::cubic::Cube;
@@ -11,14 +12,44 @@ use crate::cubic::Layout;
 use crate::cubic::OrientationKind;
 use crate::cubic::Pixel;
 use crate::cubic::pixel_to_cube;
+use crate::diplomacy::Relation;
 use crate::game::Game;
 use crate::inputs::{draw_tile_selector, draw_all_locality_names};
 use crate::map_editor::Editor;
+use crate::setup::Setup;
 use crate::world::LocalityCategory;
 use crate::world::Tile;
 use crate::world::TileCategory;
 
-fn owner_to_color(&owner: &Option<usize>) -> macroquad::color::Color {
+const TEAM_TINT: f32 = 0.35;
+
+fn team_to_color(team: usize) -> macroquad::color::Color {
+    match team % 4 {
+        0 => Color { r: 1.0, g: 0.55, b: 0.0, a: 1. },
+        1 => Color { r: 0.0, g: 0.2, b: 0.6, a: 1. },
+        2 => Color { r: 0.1, g: 0.6, b: 0.1, a: 1. },
+        _ => Color { r: 0.5, g: 0.0, b: 0.5, a: 1. },
+    }
+}
+
+// Tints the colour of every team member towards the colour of its team, so that allies look alike.
+fn owner_to_color(&owner: &Option<usize>, teams: &[Option<usize>]) -> macroquad::color::Color {
+    let color = player_color(&owner);
+    match owner.and_then(|index| teams.get(index).copied().flatten()) {
+        Some(team) => {
+            let tint = team_to_color(team);
+            Color {
+                r: color.r + (tint.r - color.r) * TEAM_TINT,
+                g: color.g + (tint.g - color.g) * TEAM_TINT,
+                b: color.b + (tint.b - color.b) * TEAM_TINT,
+                a: color.a,
+            }
+        }
+        None => color,
+    }
+}
+
+fn player_color(&owner: &Option<usize>) -> macroquad::color::Color {
     match owner {
         Some(0) => Color { r: 1.0, g: 0., b: 0., a: 0.67 },
         Some(1) => Color { r: 0.0, g: 0.47, b: 0.95, a: 0.67 },
@@ -36,6 +67,7 @@ fn owner_to_color(&owner: &Option<usize>) -> macroquad::color::Color {
 
 pub struct Assets {
     pub locality_names: Vec<String>,
+    pub cities: Vec<crate::world::gen::RealCity>,
     pub font: Font,
     pub army: Texture2D,
     pub port: Texture2D,
@@ -44,7 +76,6 @@ pub struct Assets {
     pub water_material: Material,
     pub init_layout: Layout<f32>,
     pub shape: Vec<(f32, f32)>,
-    pub river: Vec<(usize, f32, f32)>,
 }
 
 impl World {
@@ -79,7 +110,7 @@ impl World {
             }
         }
     }
-    pub fn draw_game_tiles(&self, &layout: &Layout<f32>, assets: &Assets) {
+    pub fn draw_game_tiles(&self, &layout: &Layout<f32>, assets: &Assets, teams: &[Option<usize>]) {
         let size = layout.size[0] as f32;
         let mut army_params = DrawTextureParams::default();
         army_params.dest_size = Some(Vec2{x: layout.size[0] as f32*1.5, y: layout.size[1] as f32*1.5});
@@ -97,7 +128,7 @@ impl World {
             let x = pixel.0;
             let y = pixel.1;
             if tile.owner_index.is_some() {
-                let color = owner_to_color(&tile.owner_index);
+                let color = owner_to_color(&tile.owner_index, teams);
                 let vertical = match layout.orientation {
                     OrientationKind::Pointy(_) => true,
                     OrientationKind::Flat(_) => false,
@@ -125,7 +156,7 @@ impl World {
                 }
             }
             if tile.army.is_some() {
-                let color = owner_to_color(&tile.army.as_ref().unwrap().owner_index);
+                let color = owner_to_color(&tile.army.as_ref().unwrap().owner_index, teams);
                 // draw_texture(assets.army, x - x_army_offset, y - y_army_offset, color);
                 draw_texture_ex(assets.army, x - x_army_offset, y - y_army_offset, color, army_params.clone());
             }
@@ -144,15 +175,43 @@ impl World {
 //     hexagon_rgba(context, layout, cube, color)
 
 
+// Lists the share of the map controlled by every player, grouped by team.
 fn draw_map_control_summary(game: &Game) {
     let width = macroquad::window::screen_width();
     let ratio = 0.83; // 1700 / 2048
     let mut dy = 0.;
-    for (idx, player) in game.players.iter().enumerate() {
-        let color = owner_to_color(&Some(idx));
-        let no_owned = game.world.cubes_by_ownership.get(&idx).unwrap().len();
-        let percentage = no_owned as f32 / game.world.len() as f32 * 100.;
-        let text = format!("{}: {:.2}%", player.name, percentage);
+    let current_player_index = game.current_player_index();
+    let teams = game.teams();
+    let percentage_of = |idx: usize| {
+        let no_owned = game.world.cubes_by_ownership.get(&idx).map_or(0, |cubes| cubes.len());
+        no_owned as f32 / game.world.len() as f32 * 100.
+    };
+    let mut order: Vec<usize> = (0..game.players.len()).collect();
+    order.sort_by_key(|&idx| (teams[idx].is_none(), teams[idx], idx));
+    let mut previous_team = None;
+    for idx in order {
+        let player = &game.players[idx];
+        if teams[idx].is_some() && teams[idx] != previous_team {
+            let team = teams[idx].unwrap();
+            let team_percentage: f32 = (0..game.players.len()).filter(|&i| teams[i] == Some(team)).map(percentage_of).sum();
+            let text = format!("Team {}: {:.2}%", team + 1, team_percentage);
+            let (x, mut y) = (ratio * width, 50.);
+            y += dy;
+            dy += 40.;
+            draw_text(&text, x, y, 40., team_to_color(team));
+        }
+        previous_team = teams[idx];
+        let color = owner_to_color(&Some(idx), &teams);
+        let percentage = percentage_of(idx);
+        let indent = if teams[idx].is_some() {"  "} else {""};
+        let relation = match game.diplomacy.relation(current_player_index, idx) {
+            _ if player.is_defeated => " (defeated)",
+            Relation::War => "",
+            Relation::NonAggression => " (pact)",
+            Relation::Alliance if idx == current_player_index => "",
+            Relation::Alliance => " (ally)",
+        };
+        let text = format!("{}{}: {:.2}%{}", indent, player.name, percentage, relation);
         let (x, mut y) = (ratio * width, 50.);
         y += dy;
         dy += 40.;
@@ -161,11 +220,35 @@ fn draw_map_control_summary(game: &Game) {
 }
 
 
+// Lists the proposals awaiting the current player's answer, and announces the winners.
+fn draw_diplomacy(game: &Game) {
+    let height = macroquad::window::screen_height();
+    let mut y = height - 50.;
+    if !game.winners.is_empty() {
+        let names: Vec<String> = game.winners.iter().map(|&i| game.players[i].to_string()).collect();
+        let text = format!("{} won!", names.join(" & "));
+        draw_text(&text, 50., y, 60., owner_to_color(&Some(game.winners[0]), &game.teams()));
+        return
+    }
+    for proposal in game.diplomacy.proposals_to(game.current_player_index()) {
+        let text = format!("{} proposes a {} (Y: accept, X: decline)", game.players[proposal.from], proposal.relation);
+        draw_text(&text, 50., y, 40., owner_to_color(&Some(proposal.from), &game.teams()));
+        y -= 40.;
+    }
+}
+
 pub fn draw(game: &Game, &layout: &Layout<f32>, assets: &Assets, time: f32) {
     macroquad::prelude::clear_background(macroquad::prelude::DARKGRAY);
     let has_selection = game.current_player().selection.is_some();
-    game.world.draw_base_tiles(&layout, &assets, time);
-    game.world.draw_game_tiles(&layout, &assets);
+    let viewing_player_index = game.viewing_player_index();
+    let world = game.player_view(viewing_player_index);
+    world.draw_base_tiles(&layout, &assets, time);
+    world.draw_game_tiles(&layout, &assets, &game.teams());
+    if game.fog_of_war {
+        if let Some(index) = viewing_player_index {
+            draw_fog_of_war(&game.world, index, &layout);
+        }
+    }
 
     draw_tile_selector(&layout);
 
@@ -175,45 +258,65 @@ pub fn draw(game: &Game, &layout: &Layout<f32>, assets: &Assets, time: f32) {
         draw_army_can_move_indicator(&game, &layout);
     }
 
-    draw_army_info(&game.world, &layout);
-    draw_all_locality_names(&game.world, &layout, &assets);
+    // Under fog of war, players only know about their own pockets
+    let pocket_owners: Vec<usize> = match viewing_player_index {
+        Some(index) if game.fog_of_war => vec![index],
+        _ => (0..game.players.len()).collect(),
+    };
+    draw_encircled_pockets(&world, &pocket_owners, &layout);
+
+    if game.advisor && game.current_player().ai.is_none() {
+        draw_advice(game, &layout);
+    }
+
+    if game.ai_debug {
+        if let Some(explanation) = &game.ai_explanation {
+            draw_ai_explanation(explanation, &layout);
+        }
+    }
+
+    draw_army_info(&world, &layout);
+    draw_all_locality_names(&world, &layout, &assets);
 
     draw_text(&get_fps().to_string(), 50.0, 50.0, 40., BLACK);
     draw_map_control_summary(game);
+    draw_diplomacy(game);
 
     for cs in &game.world.rivers {
         draw_river(&cs, &layout);
     }
+    // World::draw_shape_outline(shape, &layout, &assets.init_layout);
+}
 
-    // let mut shape = assets.shape.clone();
-    let mut shape = assets.river.clone();
-
-    // let x_min = shape.iter().fold(f32::NAN, |a, &b| a.min(b.1));
-    // let y_min = shape.iter().fold(f32::NAN, |a, &b| a.min(b.2));
-    // shape = shape.iter().map(|(id, x, y)| (*id, x - x_min, y - y_min)).collect();
-    // let ids: std::collections::HashSet<usize> = shape.iter().map(|(id, x, y)| *id).collect();
-    // let ids: Vec<usize> = ids.into_iter().collect();
-    // println!("ids len: {:}", ids.len());
-    // let COLORS = vec!(BEIGE, BLACK, BLUE, BROWN, DARKBLUE, DARKBROWN, DARKGREEN, DARKPURPLE, GOLD, GREEN, LIME, MAGENTA, MAROON, ORANGE, PINK, PURPLE, RED, SKYBLUE, VIOLET, WHITE, YELLOW,);
-    let COLORS = vec!(BEIGE, BLACK, BLUE, BROWN, GOLD, GREEN, LIME, MAGENTA, MAROON, ORANGE, PINK, PURPLE, RED, VIOLET, WHITE, YELLOW,);
-
-    for j in 1..shape.len() {
-        let (id, mut x, mut y) = shape[j];
-        x *= layout.size[0] / assets.init_layout.size[0];
-        y *= layout.size[1] / assets.init_layout.size[1];
-        x += layout.origin[0];
-        y += layout.origin[1];
-        // let id_idx = ids.iter().position(|id_| id == *id_).unwrap();
-        let color = COLORS[j % COLORS.len()];
-        // let color = RED;
-        draw_circle(x, y, 8., color);
+// The preview of the world, with the settings listed on the left.
+pub fn draw_setup(setup: &Setup, layout: &Layout<f32>, assets: &Assets, time: f32) {
+    macroquad::prelude::clear_background(macroquad::prelude::DARKGRAY);
+    let teams: Vec<Option<usize>> = setup.preview_players.iter().map(|player| player.team).collect();
+    setup.preview.draw_base_tiles(&layout, &assets, time);
+    setup.preview.draw_game_tiles(&layout, &assets, &teams);
+    for cs in &setup.preview.rivers {
+        draw_river(&cs, &layout);
+    }
+    draw_all_locality_names(&setup.preview, &layout, &assets);
+
+    let mut y = 50.;
+    draw_text("New game (Enter: start, Esc: quit)", 50., y, 40., WHITE);
+    for (index, row) in setup.rows().into_iter().enumerate() {
+        y += 40.;
+        let color = match row {
+            _ if index == setup.selected => YELLOW,
+            crate::setup::Row::Player(player_index) => player_color(&Some(player_index)),
+            crate::setup::Row::Team(player_index) => owner_to_color(&Some(player_index), &teams),
+            _ => WHITE,
+        };
+        draw_text(&setup.label(row), 50., y, 40., color);
     }
-    // World::draw_shape_outline(shape, &layout, &assets.init_layout);
 }
 
 pub fn draw_editor(editor: &Editor, layout: &Layout<f32>, assets: &Assets, time: f32) {
     editor.world.draw_base_tiles(&layout, &assets, time);
-    editor.world.draw_game_tiles(&layout, &assets);
+    let teams: Vec<Option<usize>> = editor.players.iter().map(|player| player.team).collect();
+    editor.world.draw_game_tiles(&layout, &assets, &teams);
 
     draw_tile_selector(&layout);
 
@@ -267,6 +370,95 @@ fn draw_army_can_move_indicator(game: &Game, &layout: &Layout<f32>) {
     )
 }
 
+// Shades every tile the player cannot currently see.
+fn draw_fog_of_war(world: &World, player_index: usize, &layout: &Layout<f32>) {
+    let size = layout.size[0];
+    let vertical = match layout.orientation {
+        OrientationKind::Pointy(_) => true,
+        OrientationKind::Flat(_) => false,
+    };
+    let color = Color::from_rgba(0, 0, 0, 96);
+    let visible_cubes = world.get_visible_cubes(player_index);
+    for cube in world.keys().filter(|cube| !visible_cubes.contains(cube)) {
+        let p = Cube::<f32>::from(*cube).to_pixel(&layout);
+        draw_hexagon(p.0, p.1, size, 0., vertical, BLACK, color);
+    }
+}
+
+// Outlines every tile that is cut off from its owner's supply sources.
+fn draw_encircled_pockets(world: &World, player_indices: &[usize], &layout: &Layout<f32>) {
+    let size = layout.size[0];
+    let vertical = match layout.orientation {
+        OrientationKind::Pointy(_) => true,
+        OrientationKind::Flat(_) => false,
+    };
+    let fill = Color::from_rgba(0, 0, 0, 0);
+    for &player_index in player_indices {
+        for cube in world.get_encircled_cubes(player_index) {
+            let p = Cube::<f32>::from(cube).to_pixel(&layout);
+            draw_hexagon(p.0, p.1, size * 0.9, size/8., vertical, MAROON, fill);
+        }
+    }
+}
+
+// Shades every cube by the best score of a move into it, green for gains and red for losses,
+// draws the chosen orders as arrows, and breaks down the scores of moves into the hovered cube.
+fn draw_ai_explanation(explanation: &TurnExplanation, &layout: &Layout<f32>) {
+    let size = layout.size[0];
+    let vertical = match layout.orientation {
+        OrientationKind::Pointy(_) => true,
+        OrientationKind::Flat(_) => false,
+    };
+    let mut best_scores: std::collections::HashMap<Cube<i32>, i32> = std::collections::HashMap::new();
+    for considered in &explanation.moves {
+        let best = best_scores.entry(considered.target).or_insert(considered.score);
+        *best = (*best).max(considered.score);
+    }
+    let max_score = best_scores.values().map(|score| score.abs()).max().unwrap_or(1).max(1) as f32;
+    for (cube, score) in &best_scores {
+        let p = Cube::<f32>::from(*cube).to_pixel(&layout);
+        let alpha = 0.15 + 0.5 * (score.abs() as f32 / max_score);
+        let color = if *score >= 0 {Color::new(0., 1., 0., alpha)} else {Color::new(1., 0., 0., alpha)};
+        draw_hexagon(p.0, p.1, size * 0.9, 0., vertical, color, color);
+    }
+
+    for considered in explanation.moves.iter().filter(|considered| considered.chosen) {
+        draw_order_arrow(&considered.origin, &considered.target, &considered.score.to_string(), &layout, BLACK);
+    }
+
+    let hovered = pixel_to_cube(&layout, mouse_position().into()).round::<i32>();
+    let mut y = 100.;
+    draw_text(&format!("AI debug, turn {}: combat/tile/border/threat/redeployment", explanation.turn), 50., y, 30., BLACK);
+    for considered in explanation.moves.iter().filter(|considered| considered.target == hovered).take(10) {
+        y += 30.;
+        let b = &considered.breakdown;
+        let text = format!("{}{:?} -> {:?}: {} = {}/{}/{}/{}/{}", if considered.chosen {"* "} else {""}, considered.origin, considered.target, considered.score, b.combat, b.tile, b.border, b.threat, b.redeployment);
+        draw_text(&text, 50., y, 30., BLACK);
+    }
+}
+
+// Draws an arrow from the origin to the target of an order, labelled halfway.
+fn draw_order_arrow(origin: &Cube<i32>, target: &Cube<i32>, label: &str, &layout: &Layout<f32>, color: Color) {
+    let size = layout.size[0];
+    let from = Cube::<f32>::from(*origin).to_pixel(&layout);
+    let to = Cube::<f32>::from(*target).to_pixel(&layout);
+    draw_line(from.0, from.1, to.0, to.1, size / 8., color);
+    let angle = (to.1 - from.1).atan2(to.0 - from.0);
+    let head = size / 3.;
+    let left = vec2(to.0 - head * (angle - PI / 6.).cos(), to.1 - head * (angle - PI / 6.).sin());
+    let right = vec2(to.0 - head * (angle + PI / 6.).cos(), to.1 - head * (angle + PI / 6.).sin());
+    draw_triangle(vec2(to.0, to.1), left, right, color);
+    draw_text(label, (from.0 + to.0) / 2., (from.1 + to.1) / 2., size, color);
+}
+
+// Shows the advisor's suggested orders, ranked, with their expected gains.
+fn draw_advice(game: &Game, &layout: &Layout<f32>) {
+    for (rank, suggestion) in game.advice.iter().enumerate() {
+        let label = format!("#{} +{}", rank + 1, suggestion.score);
+        draw_order_arrow(&suggestion.origin, &suggestion.target, &label, &layout, GOLD);
+    }
+}
+
 fn draw_army_legal_moves(game: &Game, &layout: &Layout<f32>) {
     // let selection = game.current_player().selection;
     let size = layout.size[0];
@@ -5,13 +5,23 @@
 // this is stored as CubeSide(int: (1, 0, 0), half: (false, true, true), sign: (true, false, true))
 
 use macroquad::miniquad::start;
-use rand::seq::index::sample;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::rng;
 
 use crate::Cube;
 use crate::DIRECTIONS;
-use crate::REV_DIRECTIONS;
+use crate::cubic::Layout;
+use crate::cubic::Pixel;
+use crate::cubic::pixel_to_cube;
+use crate::TileCategory;
+use crate::World;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::ops::Add;
 use std::ops::Deref;
 use std::ops::Index;
@@ -58,7 +68,6 @@ impl CubeSide {
         let s = Self {int: Cube::new(q_int, r_int),
               half: Cube::new(q_half, r_half),
               sign: Cube::new(q_sign, r_sign)};
-        println!("{:}", s);
         s
     }
     pub fn int(self) -> Cube<i32> {
@@ -90,6 +99,19 @@ impl From<Cube<f32>> for CubeSide {
     }
 }
 
+// Saved as the position halfway between the two cubes, e.g. "1_-0.5".
+impl Serialize for CubeSide {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        Cube::<f32>::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CubeSide {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(CubeSide::from(Cube::<f32>::deserialize(deserializer)?))
+    }
+}
+
 impl Display for CubeSide {
     fn fmt(&self, f: &mut Formatter) -> Result {
         let cube_float = Cube::<f32>::from(self);
@@ -153,46 +175,150 @@ impl Mul<i32> for CubeSide {
     }
 }
 
-// generate a continuous length of hexagon segments
-//
-// pick a random tile as origin
-// keep adding segments of the origin hex in clockwise manner until a random roll
-// advances the origin to a new hex, in the direction of the current segment.
-// repeat last step, reversing the clockwise direction
-pub fn generate_river(land_tiles: HashSet<&Cube<i32>>, max_length: usize, threshold: f32) -> HashSet<CubeSide> {
+// A corner where three hexes meet, stored as its three cubes in sorted order.
+// Rivers run from corner to corner along the side shared by two of the cubes.
+type Vertex = [Cube<i32>; 3];
+
+// Sources must be at least this many sides away from the water.
+const MIN_RIVER_LENGTH: i32 = 3;
+
+fn vertex(a: Cube<i32>, b: Cube<i32>, c: Cube<i32>) -> Vertex {
+    let mut vertex = [a, b, c];
+    vertex.sort();
+    vertex
+}
+
+fn side(a: Cube<i32>, b: Cube<i32>) -> CubeSide {
+    CubeSide::from(Cube::<f32>::from(a) + (b - a) / 2)
+}
+
+/// The three corners reached by following each side of the vertex, with the two cubes along that side.
+fn vertex_neighbours(vertex: &Vertex) -> Vec<(Vertex, Cube<i32>, Cube<i32>)> {
+    [(0, 1, 2), (1, 2, 0), (0, 2, 1)].iter().map(|&(i, j, k)| {
+        let (a, b, c) = (vertex[i], vertex[j], vertex[k]);
+        let dir_idx = DIRECTIONS.iter().position(|dir| *dir == b - a).unwrap();
+        // a and b share two neighbours, c and the one across the side
+        let d = [a + DIRECTIONS[(dir_idx + 1) % 6], a + DIRECTIONS[(dir_idx + 5) % 6]]
+            .into_iter().find(|d| *d != c).unwrap();
+        (self::vertex(a, b, d), a, b)
+    }).collect()
+}
+
+// Rivers originate inland and flow along the tile sides towards the nearest water,
+// following the distance from every corner to the water. A river never crosses itself,
+// and joins another river as a tributary when it reaches it. The meander chance lets it
+// wander sideways, at the same distance from the water, instead of flowing downhill.
+// Maps without water drain over their edge instead.
+pub fn generate_rivers(world: &World, count: usize, meander: f32) -> HashSet<CubeSide> {
+    let is_water = |cube: &Cube<i32>| world.get(cube).is_some_and(|t| matches!(t.category, TileCategory::Water));
+    let is_land = |cube: &Cube<i32>| world.get(cube).is_some_and(|t| !matches!(t.category, TileCategory::Water));
+    let drains_over_edge = !world.values().any(|t| matches!(t.category, TileCategory::Water));
+    let is_sink = |vertex: &Vertex| vertex.iter().any(|c| is_water(c) || (drains_over_edge && !world.contains_key(c)));
+    let is_inland = |vertex: &Vertex| vertex.iter().all(is_land);
+
+    // Distance from every corner to the water, searched outwards from the sinks
+    let mut sinks: Vec<Vertex> = world.keys().flat_map(|cube| {
+        (0..6).map(move |i| vertex(*cube, *cube + DIRECTIONS[i], *cube + DIRECTIONS[(i + 1) % 6]))
+    }).filter(is_sink).collect();
+    sinks.sort();
+    sinks.dedup();
+    let mut distances: HashMap<Vertex, i32> = sinks.iter().map(|v| (*v, 0)).collect();
+    let mut frontier: VecDeque<Vertex> = sinks.into_iter().collect();
+    while let Some(current) = frontier.pop_front() {
+        let distance = distances[&current];
+        for (neighbour, _, _) in vertex_neighbours(&current) {
+            if is_inland(&neighbour) && !distances.contains_key(&neighbour) {
+                distances.insert(neighbour, distance + 1);
+                frontier.push_back(neighbour);
+            }
+        }
+    }
+
+    // Sorted, so that the same seed always picks the same sources
+    let mut sources: Vec<Vertex> = distances.iter().filter(|(_, &d)| d >= MIN_RIVER_LENGTH).map(|(v, _)| *v).collect();
+    sources.sort();
+    rng::with_rng(|rng| sources.shuffle(rng));
+
+    let mut river = HashSet::new();
+    let mut river_vertices: HashSet<Vertex> = HashSet::new();
+    let mut generated = 0;
+    for source in sources {
+        if generated >= count {break}
+        if river_vertices.contains(&source) {continue}
+        let mut current = source;
+        let mut visited = HashSet::from([source]);
+        loop {
+            let distance = distances[&current];
+            if distance == 0 || (current != source && river_vertices.contains(&current)) {break}
+            let neighbours: Vec<(Vertex, Cube<i32>, Cube<i32>)> = vertex_neighbours(&current).into_iter()
+                .filter(|(v, _, _)| distances.contains_key(v) && !visited.contains(v))
+                .collect();
+            let downhill: Vec<_> = neighbours.iter().filter(|(v, _, _)| distances[v] < distance).collect();
+            let sideways: Vec<_> = neighbours.iter().filter(|(v, _, _)| distances[v] == distance).collect();
+            let next = match !sideways.is_empty() && rng::random::<f32>() < meander {
+                true => rng::with_rng(|rng| sideways.choose(rng).copied()),
+                false => rng::with_rng(|rng| downhill.choose(rng).copied()),
+            };
+            let Some((next, a, b)) = next else {break};
+            river.insert(side(*a, *b));
+            visited.insert(*next);
+            current = *next;
+        }
+        river_vertices.extend(visited);
+        generated += 1;
+    }
+    river
+}
+
+fn vertex_pixel(vertex: &Vertex, layout: &Layout<f32>) -> Pixel<f32> {
+    let pixels: Vec<Pixel<f32>> = vertex.iter().map(|cube| Cube::<f32>::from(*cube).to_pixel(layout)).collect();
+    Pixel(pixels.iter().map(|p| p.0).sum::<f32>() / 3., pixels.iter().map(|p| p.1).sum::<f32>() / 3.)
+}
+
+/// The corner of a hex nearest to the point.
+fn nearest_vertex(point: Pixel<f32>, layout: &Layout<f32>) -> Vertex {
+    let cube: Cube<i32> = pixel_to_cube(layout, [point.0, point.1]).round();
+    let distance = |vertex: &Vertex| {
+        let pixel = vertex_pixel(vertex, layout);
+        (pixel.0 - point.0).powi(2) + (pixel.1 - point.1).powi(2)
+    };
+    (0..6).map(|i| vertex(cube, cube + DIRECTIONS[i], cube + DIRECTIONS[(i + 1) % 6]))
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap()
+}
+
+/// The sides along a shortest path between two corners.
+fn sides_between(from: Vertex, to: Vertex) -> Vec<CubeSide> {
+    let mut parents: HashMap<Vertex, (Vertex, CubeSide)> = HashMap::new();
+    let mut frontier = VecDeque::from([from]);
+    let mut visited = HashSet::from([from]);
+    while let Some(current) = frontier.pop_front() {
+        if current == to {break}
+        for (neighbour, a, b) in vertex_neighbours(&current) {
+            if visited.insert(neighbour) {
+                parents.insert(neighbour, (current, side(a, b)));
+                frontier.push_back(neighbour);
+            }
+        }
+    }
+    let mut sides = vec!();
+    let mut current = to;
+    while let Some((parent, side)) = parents.get(&current) {
+        sides.push(*side);
+        current = *parent;
+    }
+    sides
+}
+
+/// Snaps polylines, given in the pixels of the layout, onto the sides of its hexes.
+pub fn trace_rivers(lines: &[Vec<Pixel<f32>>], layout: &Layout<f32>) -> HashSet<CubeSide> {
     let mut river = HashSet::new();
-    // pick a random starting position
-    let starting_pos_idx = sample(&mut rand::thread_rng(), land_tiles.len(), 1).index(0);
-    let mut origin: Cube<i32> = *land_tiles.iter().skip(starting_pos_idx).next().unwrap().clone();
-    let mut reverse = true;
-    let mut d = REV_DIRECTIONS;
-    let mut dirs = DIRECTIONS.iter().cycle().skip(6);
-    let mut next_direction = dirs.next().unwrap().clone();
-    let mut current_dir = next_direction;
-    // add half of the direction
-    let mut next_pos = CubeSide::from(Cube::<f32>::from(origin) + (next_direction / 2));
-    for _ in 0..max_length {
-        river.insert(next_pos);
-        let advance_a_tile = rand::random::<f32>();
-        if advance_a_tile > threshold {
-            // advance to a new tile in the direction of the current segment
-            origin += current_dir;
-
-            // if !land_tiles.contains(&origin) {break}
-
-            // at this new origin, the direction represented by the current segment is mirrored
-            current_dir = current_dir * -1;
-
-            // reverse the iterator on every tile advancement
-            // find the current direction within the new iterator, and skip to it
-            d = if reverse {REV_DIRECTIONS} else {DIRECTIONS};
-            let idx = d.iter().position(|&c| c == (current_dir)).unwrap();
-            dirs = d.iter().cycle().skip(idx + 1);
-            reverse = !reverse;
+    for line in lines {
+        let mut vertices: Vec<Vertex> = line.iter().map(|point| nearest_vertex(*point, layout)).collect();
+        vertices.dedup();
+        for pair in vertices.windows(2) {
+            river.extend(sides_between(pair[0], pair[1]));
         }
-        next_direction = dirs.next().unwrap().clone();
-        next_pos = CubeSide::from(Cube::<f32>::from(origin) + (next_direction / 2));
-        current_dir = next_direction;
     }
     river
 }
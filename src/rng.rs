@@ -0,0 +1,26 @@
+//! Shared random number generator, so that generated worlds can be replayed from a seed.
+
+use std::cell::RefCell;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::distributions::Distribution;
+use rand::distributions::Standard;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Reseeds the generator. Everything drawn afterwards is reproducible.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn random<T>() -> T where Standard: Distribution<T> {
+    with_rng(|rng| rng.gen())
+}
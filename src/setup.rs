@@ -0,0 +1,313 @@
+//! The settings screen shown before a game. Every change regenerates a preview
+//! of the world, and the game starts on exactly the previewed world.
+
+use strum::IntoEnumIterator;
+
+use crate::ai::AI;
+use crate::ai::Difficulty;
+use crate::ai::Personality;
+use crate::cubic::Layout;
+use crate::game::Game;
+use crate::game::VictoryCondition;
+use crate::mquad::Assets;
+use crate::rng;
+use crate::world::gen::*;
+use crate::Player;
+use crate::World;
+
+const MIN_PLAYERS: usize = 2;
+const MAX_PLAYERS: usize = 8;
+const MIN_SIZE: i32 = 3;
+const MAX_SIZE: i32 = 30;
+const MAX_RIVERS: usize = 30;
+const WATER_RATIO_STEP: f32 = 0.05;
+const DENSITY_STEP: f32 = 0.02;
+const MAX_NAME_LENGTH: usize = 20;
+// Team colours repeat after four teams.
+const MAX_TEAMS: usize = 4;
+// Shares of the world a coalition must hold to win by territory.
+const TERRITORY_TARGETS: [f32; 3] = [0.3, 0.5, 0.7];
+const DEFAULT_NAMES: [&str; MAX_PLAYERS] = ["Redosia", "Bluegaria", "Greenland", "Violetnam", "Orangola", "Pinkistan", "Cyanada", "Yellowstan"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Controller {
+    Human,
+    Bot(Difficulty, Personality),
+}
+
+impl Controller {
+    /// Human first, then every difficulty and personality of the built-in AI.
+    fn all() -> Vec<Controller> {
+        let mut controllers = vec![Controller::Human];
+        for difficulty in Difficulty::iter() {
+            for personality in Personality::iter() {
+                controllers.push(Controller::Bot(difficulty, personality));
+            }
+        }
+        controllers
+    }
+}
+
+pub struct PlayerSetup {
+    pub name: String,
+    pub controller: Controller,
+    // Teammates start allied.
+    pub team: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Row {
+    Shape,
+    Size,
+    Terrain,
+    WaterRatio,
+    Rivers,
+    Localities,
+    Density,
+    Capitals,
+    Seed,
+    FogOfWar,
+    Victory,
+    PlayerCount,
+    Player(usize),
+    Team(usize),
+}
+
+pub struct Setup {
+    pub settings: WorldSettings,
+    pub players: Vec<PlayerSetup>,
+    pub selected: usize,
+    pub preview: World,
+    pub preview_players: Vec<Player>,
+    pub fog_of_war: bool,
+    // The share of the world to hold, or None to win by elimination.
+    pub territory_target: Option<f32>,
+    locality_names: Vec<String>,
+    init_layout: Layout<f32>,
+}
+
+impl Setup {
+    pub fn new(assets: &Assets) -> Self {
+        let controllers = [
+            Controller::Human,
+            Controller::Bot(Difficulty::Normal, Personality::Expansionist),
+            Controller::Bot(Difficulty::Hard, Personality::Balanced),
+            Controller::Bot(Difficulty::Easy, Personality::Aggressive),
+        ];
+        let players = controllers.iter().zip(DEFAULT_NAMES).map(|(controller, name)| {
+            PlayerSetup{name: name.to_string(), controller: *controller, team: None}
+        }).collect();
+        let settings = WorldSettings{
+            seed: rng::random::<u32>() as u64,
+            custom_shape: assets.shape.clone(),
+            real_cities: assets.cities.clone(),
+            ..Default::default()
+        };
+        let mut setup = Setup{
+            settings,
+            players,
+            selected: 0,
+            preview: World::new(),
+            preview_players: vec!(),
+            fog_of_war: true,
+            territory_target: Some(TERRITORY_TARGETS[0]),
+            locality_names: assets.locality_names.clone(),
+            init_layout: assets.init_layout.clone(),
+        };
+        setup.refresh_preview();
+        setup
+    }
+
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = vec![
+            Row::Shape, Row::Size, Row::Terrain, Row::WaterRatio, Row::Rivers,
+            Row::Localities, Row::Density, Row::Capitals, Row::Seed,
+            Row::FogOfWar, Row::Victory, Row::PlayerCount,
+        ];
+        rows.extend((0..self.players.len()).flat_map(|index| [Row::Player(index), Row::Team(index)]));
+        rows
+    }
+
+    pub fn label(&self, row: Row) -> String {
+        let settings = &self.settings;
+        match row {
+            Row::Shape => format!("Shape: {:?}", settings.shape),
+            Row::Size => match settings.shape {
+                ShapeKind::Hexagonal => format!("Radius: {}", settings.size),
+                ShapeKind::Classic => format!("Board: {}x{}", 2 * settings.size + 4, settings.size + 3),
+                ShapeKind::Custom => "Size: set by the shape".to_string(),
+            },
+            Row::Terrain => match settings.terrain {
+                Some(preset) => format!("Terrain: {:?}", preset),
+                None => "Terrain: All land".to_string(),
+            },
+            Row::WaterRatio => format!("Water: {:.0}%", settings.water_ratio * 100.),
+            Row::Rivers => format!("Rivers: {}", settings.rivers),
+            Row::Localities => format!("Localities: {:?}", settings.locality_placement),
+            Row::Density => format!("Locality density: {:.0}%", settings.localities.density * 100.),
+            Row::Capitals => match settings.locality_placement {
+                LocalitiesKind::Real => "Capitals: Biggest cities".to_string(),
+                _ => format!("Capitals: {:?}", settings.capitals),
+            },
+            Row::Seed => format!("Seed: {}", settings.seed),
+            Row::FogOfWar => format!("Fog of war: {}", if self.fog_of_war {"On"} else {"Off"}),
+            Row::Victory => match self.territory_target {
+                Some(target) => format!("Victory: {:.0}% of the territory", target * 100.),
+                None => "Victory: Elimination".to_string(),
+            },
+            Row::PlayerCount => format!("Players: {}", self.players.len()),
+            Row::Player(index) => {
+                let player = &self.players[index];
+                match player.controller {
+                    Controller::Human => format!("{}: Human", player.name),
+                    Controller::Bot(difficulty, personality) => format!("{}: {} {} AI", player.name, difficulty, personality),
+                }
+            }
+            Row::Team(index) => match self.players[index].team {
+                Some(team) => format!("    Team {}", team + 1),
+                None => "    No team".to_string(),
+            },
+        }
+    }
+
+    pub fn select(&mut self, step: i32) {
+        let count = self.rows().len() as i32;
+        self.selected = (self.selected as i32 + step).rem_euclid(count) as usize;
+    }
+
+    /// Steps the selected setting forwards or backwards, and regenerates the preview.
+    pub fn change(&mut self, step: i32) {
+        let row = self.rows()[self.selected];
+        let settings = &mut self.settings;
+        match row {
+            Row::Shape => {
+                settings.shape = cycle(&ShapeKind::iter().collect::<Vec<_>>(), settings.shape, step);
+                if !matches!(settings.shape, ShapeKind::Custom) && matches!(settings.locality_placement, LocalitiesKind::Real) {
+                    settings.locality_placement = LocalitiesKind::Random;
+                }
+            }
+            Row::Size => settings.size = (settings.size + step).clamp(MIN_SIZE, MAX_SIZE),
+            Row::Terrain => {
+                let terrains: Vec<Option<TerrainPreset>> = std::iter::once(None).chain(TerrainPreset::iter().map(Some)).collect();
+                settings.terrain = cycle(&terrains, settings.terrain, step);
+            }
+            Row::WaterRatio => settings.water_ratio = (settings.water_ratio + step as f32 * WATER_RATIO_STEP).clamp(0., 0.9),
+            Row::Rivers => settings.rivers = (settings.rivers as i32 + step).clamp(0, MAX_RIVERS as i32) as usize,
+            Row::Localities => {
+                // Real cities only fit the custom shape
+                let placements: Vec<LocalitiesKind> = LocalitiesKind::iter().filter(|kind| {
+                    !matches!(kind, LocalitiesKind::Real) || matches!(settings.shape, ShapeKind::Custom)
+                }).collect();
+                settings.locality_placement = cycle(&placements, settings.locality_placement, step);
+            }
+            Row::Density => settings.localities.density = (settings.localities.density + step as f32 * DENSITY_STEP).clamp(DENSITY_STEP, 0.5),
+            Row::Capitals => settings.capitals = cycle(&CapitalsKind::iter().collect::<Vec<_>>(), settings.capitals, step),
+            Row::Seed => settings.seed = settings.seed.wrapping_add_signed(step as i64),
+            Row::FogOfWar => self.fog_of_war = !self.fog_of_war,
+            Row::Victory => {
+                let targets: Vec<Option<f32>> = TERRITORY_TARGETS.into_iter().map(Some).chain(std::iter::once(None)).collect();
+                self.territory_target = cycle(&targets, self.territory_target, step);
+            }
+            Row::PlayerCount => {
+                let count = (self.players.len() as i32 + step).clamp(MIN_PLAYERS as i32, MAX_PLAYERS as i32) as usize;
+                self.players.truncate(count);
+                while self.players.len() < count {
+                    let name = DEFAULT_NAMES[self.players.len()].to_string();
+                    self.players.push(PlayerSetup{name, controller: Controller::Bot(Difficulty::Normal, Personality::Balanced), team: None});
+                }
+            }
+            Row::Player(index) => {
+                let player = &mut self.players[index];
+                player.controller = cycle(&Controller::all(), player.controller, step);
+            }
+            Row::Team(index) => {
+                let teams: Vec<Option<usize>> = std::iter::once(None).chain((0..MAX_TEAMS).map(Some)).collect();
+                let player = &mut self.players[index];
+                player.team = cycle(&teams, player.team, step);
+            }
+        }
+        self.refresh_preview();
+    }
+
+    /// Typing edits the name of the selected player, or the seed.
+    pub fn type_char(&mut self, character: char) {
+        match self.rows()[self.selected] {
+            Row::Seed => if let Some(digit) = character.to_digit(10) {
+                self.settings.seed = self.settings.seed.saturating_mul(10).saturating_add(digit as u64);
+                self.refresh_preview();
+            },
+            Row::Player(index) => {
+                let name = &mut self.players[index].name;
+                if !character.is_control() && name.chars().count() < MAX_NAME_LENGTH {
+                    name.push(character);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        match self.rows()[self.selected] {
+            Row::Seed => {
+                self.settings.seed /= 10;
+                self.refresh_preview();
+            }
+            Row::Player(index) => {self.players[index].name.pop();}
+            _ => (),
+        }
+    }
+
+    fn build_players(&self) -> Vec<Player> {
+        self.players.iter().map(|player| {
+            let bot = match player.controller {
+                Controller::Human => None,
+                Controller::Bot(difficulty, personality) => Some(AI::from_preset(difficulty, personality).into()),
+            };
+            let mut new_player = Player::new(&player.name, bot);
+            new_player.team = player.team;
+            new_player
+        }).collect()
+    }
+
+    fn generate(&self, players: &mut Vec<Player>) -> World {
+        let mut world = World::new();
+        world.generate_with_settings(&self.settings, players, &mut self.locality_names.iter().map(|s| &**s).collect(), &self.init_layout);
+        world
+    }
+
+    pub fn refresh_preview(&mut self) {
+        let mut players = self.build_players();
+        self.preview = self.generate(&mut players);
+        self.preview_players = players;
+    }
+
+    /// The settings are seeded, so the game is played on the previewed world.
+    pub fn new_game(&self, assets: &Assets) -> Game {
+        let victory_condition = match self.territory_target {
+            Some(target) => VictoryCondition::Territory(target),
+            None => VictoryCondition::Elimination,
+        };
+        let mut game = Game::new(self.build_players(), World::new(), victory_condition);
+        game.fog_of_war = self.fog_of_war;
+        game.init_world(assets, &self.settings);
+        game.form_teams();
+        game
+    }
+}
+
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, step: i32) -> T {
+    let index = values.iter().position(|value| *value == current).unwrap_or(0) as i32;
+    values[(index + step).rem_euclid(values.len() as i32) as usize]
+}
+
+impl crate::Component for Setup {
+    fn draw(&self, &layout: &Layout<f32>, assets: &Assets, time: f32) {
+        crate::draw_setup(self, &layout, assets, time);
+    }
+    fn poll(&mut self, layout: &mut Layout<f32>) -> bool {
+        crate::poll_setup_inputs(self, layout)
+    }
+    fn update(&mut self) {
+        {}
+    }
+}
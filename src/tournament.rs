@@ -0,0 +1,318 @@
+//! Headless self-play tournaments between AI configurations.
+//! Many seeded games are played without a window, and the results are written
+//! to CSV and JSON so that changes to the AI can be compared objectively.
+
+use std::fs::File;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::agent::Bot;
+use crate::agent::ExternalBot;
+use crate::ai::AI;
+use crate::ai::Difficulty;
+use crate::ai::Personality;
+use crate::ai::Scores;
+use crate::cubic;
+use crate::game::Game;
+use crate::game::VictoryCondition;
+use crate::map_editor::Editor;
+use crate::rng;
+use crate::world::TileCategory;
+use crate::world::gen::*;
+use crate::Player;
+use crate::World;
+
+// Chance for a generated river to meander, as used by RiverGen::Random
+const RIVER_MEANDER: f32 = 0.3;
+
+/// One AI configuration taking part in the tournament.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entrant {
+    pub name: String,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub personality: Personality,
+    // Replaces the scores of the personality, e.g. with tuned weights.
+    #[serde(default)]
+    pub scores: Option<Scores>,
+    // Same as scores, but read from a preset file saved by the tuner.
+    #[serde(default)]
+    pub scores_file: Option<String>,
+    // Plays with an external bot instead of the built-in AI.
+    #[serde(default)]
+    pub external: Option<ExternalBot>,
+}
+
+impl Entrant {
+    /// Entrants search without a time budget, so that every game replays from its seed.
+    pub fn ai(&self) -> AI {
+        let mut ai = AI::from_preset(self.difficulty, self.personality);
+        ai.strategy = ai.strategy.untimed();
+        if let Some(path) = &self.scores_file {
+            ai.scores = Scores::from_json(path);
+        }
+        if let Some(scores) = &self.scores {
+            ai.scores = scores.clone();
+        }
+        ai
+    }
+    pub fn bot(&self) -> Bot {
+        match &self.external {
+            Some(external) => Bot::External(external.clone()),
+            None => Bot::Scorer(self.ai()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum MapSpec {
+    /// A hexagonal world generated from the seed of the game, all land unless a terrain preset is given.
+    Generated{
+        radius: i32,
+        rivers: usize,
+        #[serde(default)]
+        terrain: Option<TerrainPreset>,
+        #[serde(default)]
+        water_ratio: f32,
+    },
+    /// A rectangular all land board with capitals at its corners, edges and centre, for 2 to 8 seats.
+    Classic{width: i32, height: i32},
+    /// A map saved by the editor, with one player per seat.
+    Scenario(String),
+}
+
+#[derive(Deserialize)]
+pub struct TournamentConfig {
+    pub entrants: Vec<Entrant>,
+    // Seats per game. Entrants take turns sitting out and rotate seats, defaults to all of them.
+    #[serde(default)]
+    pub players_per_game: Option<usize>,
+    pub games: usize,
+    pub max_rounds: usize,
+    // Game n is played with seed + n.
+    #[serde(default)]
+    pub seed: u64,
+    pub maps: Vec<MapSpec>,
+    pub victory_condition: VictoryCondition,
+    #[serde(default)]
+    pub fog_of_war: bool,
+    pub output: String,
+}
+
+#[derive(Serialize)]
+pub struct GameResult {
+    pub game: usize,
+    pub seed: u64,
+    pub map: usize,
+    // The entrant index of every seat.
+    pub seats: Vec<usize>,
+    // Seats that won. Empty if the game hit the round cap.
+    pub winners: Vec<usize>,
+    pub rounds: usize,
+    // Share of the land held by every seat, at the start of every round.
+    pub territory: Vec<Vec<f32>>,
+}
+
+#[derive(Serialize)]
+pub struct EntrantSummary {
+    pub name: String,
+    pub games: usize,
+    pub wins: usize,
+    pub win_rate: f32,
+    pub average_rounds: f32,
+    // Average share of the land held at the start of every round, over the games that lasted that long.
+    pub territory_curve: Vec<f32>,
+}
+
+/// Reads the config, plays every game and writes the results to the output directory.
+pub fn run(config_path: &str) {
+    let f = File::open(config_path).expect("file should open read only");
+    let config: TournamentConfig = serde_json::from_reader(f).expect("file should be proper JSON");
+    let results = play_tournament(&config);
+    write_results(&config, &results);
+}
+
+pub fn play_tournament(config: &TournamentConfig) -> Vec<GameResult> {
+    let entrant_count = config.entrants.len();
+    let seat_count = config.players_per_game.unwrap_or(entrant_count).min(entrant_count);
+    (0..config.games).map(|game_index| {
+        let seats: Vec<usize> = (0..seat_count).map(|seat| (game_index + seat) % entrant_count).collect();
+        let seed = config.seed + game_index as u64;
+        let map = game_index % config.maps.len();
+        let result = play_game(config, game_index, seed, map, seats);
+        let winners: Vec<&str> = result.winners.iter().map(|&seat| config.entrants[result.seats[seat]].name.as_str()).collect();
+        match winners.is_empty() {
+            true => println!("Game {}/{} (seed {}): draw after {} rounds", game_index + 1, config.games, seed, result.rounds),
+            false => println!("Game {}/{} (seed {}): {} won after {} rounds", game_index + 1, config.games, seed, winners.join(" & "), result.rounds),
+        }
+        result
+    }).collect()
+}
+
+fn setup_world(map: &MapSpec, players: &mut Vec<Player>) -> World {
+    match map {
+        MapSpec::Generated{radius, rivers, terrain, water_ratio} => {
+            let init_layout = cubic::Layout{orientation: cubic::OrientationKind::Flat(cubic::FLAT), size: [32., 32.], origin: [0., 0.]};
+            let locality_names = crate::load_locality_names();
+            let mut world = World::new();
+            world.generate(
+                players,
+                ShapeGen::Hexagonal(*radius),
+                match terrain {
+                    Some(preset) => TerrainGen::Noise(*preset, *water_ratio),
+                    None => TerrainGen::Land,
+                },
+                RiverGen::Random(*rivers, RIVER_MEANDER),
+                LocalitiesGen::Random(LocalitySettings::default()),
+                CapitalsGen::Random(BalanceSettings::default()),
+                &mut locality_names.iter().map(|s| &**s).collect(),
+                &init_layout,
+            );
+            world
+        }
+        MapSpec::Classic{width, height} => {
+            let init_layout = cubic::Layout{orientation: cubic::OrientationKind::Flat(cubic::FLAT), size: [32., 32.], origin: [0., 0.]};
+            let locality_names = crate::load_locality_names();
+            let mut world = World::new();
+            world.generate(
+                players,
+                ShapeGen::Classic(*width, *height),
+                TerrainGen::Land,
+                RiverGen::Random(0, RIVER_MEANDER),
+                LocalitiesGen::RandomOts(LocalitySettings::default()),
+                CapitalsGen::Classic,
+                &mut locality_names.iter().map(|s| &**s).collect(),
+                &init_layout,
+            );
+            world
+        }
+        MapSpec::Scenario(path) => {
+            let editor = Editor::from_json(path);
+            assert_eq!(editor.players.len(), players.len(), "scenario {} should have one player per seat", path);
+            for (player, scenario_player) in players.iter_mut().zip(editor.players) {
+                player.capital_pos = scenario_player.capital_pos;
+                player.team = scenario_player.team;
+            }
+            editor.world
+        }
+    }
+}
+
+fn play_game(config: &TournamentConfig, game_index: usize, seed: u64, map: usize, seats: Vec<usize>) -> GameResult {
+    rng::seed(seed);
+    let mut players: Vec<Player> = seats.iter().map(|&entrant| {
+        let entrant = &config.entrants[entrant];
+        Player::new(&entrant.name, Some(entrant.bot()))
+    }).collect();
+    let world = setup_world(&config.maps[map], &mut players);
+
+    let mut game = Game::new(players, world, config.victory_condition.clone());
+    game.fog_of_war = config.fog_of_war;
+    game.ai_order_delay = 0.;
+    // Keeps the log down to one line per game
+    game.set_headless(true);
+    game.form_teams();
+
+    let mut territory = vec![territory_shares(&game)];
+    while game.winners.is_empty() && round(&game) <= config.max_rounds {
+        game._update();
+        if round(&game) > territory.len() {
+            territory.push(territory_shares(&game));
+        }
+    }
+    GameResult{game: game_index, seed, map, seats, winners: game.winners.clone(), rounds: territory.len(), territory}
+}
+
+fn round(game: &Game) -> usize {
+    (game.turn - 1) as usize / game.players.len() + 1
+}
+
+fn territory_shares(game: &Game) -> Vec<f32> {
+    let land = game.world.values().filter(|tile| !matches!(tile.category, TileCategory::Water)).count().max(1);
+    (0..game.players.len()).map(|index| {
+        game.world.cubes_by_ownership.get(&index).map_or(0, |cubes| cubes.len()) as f32 / land as f32
+    }).collect()
+}
+
+pub fn summarize(config: &TournamentConfig, results: &[GameResult]) -> Vec<EntrantSummary> {
+    config.entrants.iter().enumerate().map(|(entrant_index, entrant)| {
+        let mut games = 0;
+        let mut wins = 0;
+        let mut total_rounds = 0;
+        let mut curve_sums: Vec<f32> = vec!();
+        let mut curve_counts: Vec<usize> = vec!();
+        for result in results {
+            for (seat, _) in result.seats.iter().enumerate().filter(|(_, &e)| e == entrant_index) {
+                games += 1;
+                total_rounds += result.rounds;
+                if result.winners.contains(&seat) {wins += 1}
+                for (round, shares) in result.territory.iter().enumerate() {
+                    if curve_sums.len() <= round {
+                        curve_sums.push(0.);
+                        curve_counts.push(0);
+                    }
+                    curve_sums[round] += shares[seat];
+                    curve_counts[round] += 1;
+                }
+            }
+        }
+        EntrantSummary{
+            name: entrant.name.clone(),
+            games,
+            wins,
+            win_rate: wins as f32 / games.max(1) as f32,
+            average_rounds: total_rounds as f32 / games.max(1) as f32,
+            territory_curve: curve_sums.iter().zip(curve_counts).map(|(sum, count)| sum / count as f32).collect(),
+        }
+    }).collect()
+}
+
+/// Writes games.csv (one row per game), territory.csv (one row per seat and round) and summary.json.
+fn write_results(config: &TournamentConfig, results: &[GameResult]) {
+    std::fs::create_dir_all(&config.output).expect("output directory should be creatable");
+    let names = |seats: &[usize]| seats.iter().map(|&e| config.entrants[e].name.clone()).collect::<Vec<String>>().join(";");
+
+    let mut games = csv::Writer::from_path(format!("{}/games.csv", config.output)).expect("Failed to open the file.");
+    games.write_record(["game", "seed", "map", "rounds", "seats", "winners"]).unwrap();
+    for result in results {
+        let winners: Vec<usize> = result.winners.iter().map(|&seat| result.seats[seat]).collect();
+        games.write_record([
+            result.game.to_string(),
+            result.seed.to_string(),
+            result.map.to_string(),
+            result.rounds.to_string(),
+            names(&result.seats),
+            names(&winners),
+        ]).unwrap();
+    }
+    games.flush().unwrap();
+
+    let mut territory = csv::Writer::from_path(format!("{}/territory.csv", config.output)).expect("Failed to open the file.");
+    territory.write_record(["game", "round", "seat", "entrant", "territory"]).unwrap();
+    for result in results {
+        for (round, shares) in result.territory.iter().enumerate() {
+            for (seat, share) in shares.iter().enumerate() {
+                territory.write_record([
+                    result.game.to_string(),
+                    (round + 1).to_string(),
+                    seat.to_string(),
+                    config.entrants[result.seats[seat]].name.clone(),
+                    share.to_string(),
+                ]).unwrap();
+            }
+        }
+    }
+    territory.flush().unwrap();
+
+    let summary = summarize(config, results);
+    let file = File::create(format!("{}/summary.json", config.output)).expect("Failed to open the file.");
+    match serde_json::to_writer_pretty(file, &summary) {
+        Ok(()) => println!("Tournament results saved to {}", config.output),
+        Err(e) => eprintln!("Error during serialization: {}", e),
+    }
+    for entrant in summary {
+        println!("{}: {}/{} wins ({:.0}%), {:.1} rounds on average", entrant.name, entrant.wins, entrant.games, entrant.win_rate * 100., entrant.average_rounds);
+    }
+}
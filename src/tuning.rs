@@ -0,0 +1,161 @@
+//! Evolutionary tuning of the AI score weights.
+//! A genetic algorithm evolves a population of Scores by playing headless
+//! tournament games against fixed opponents, and saves the fittest weights
+//! as a preset file that can be loaded with Scores::from_json().
+
+use std::fs::File;
+use std::path::Path;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::ai::Scores;
+use crate::game::VictoryCondition;
+use crate::tournament;
+use crate::tournament::Entrant;
+use crate::tournament::GameResult;
+use crate::tournament::MapSpec;
+use crate::tournament::TournamentConfig;
+
+// How much a won game counts against the share of the land held at the end.
+const FITNESS_WIN_WEIGHT: f32 = 1.;
+const FITNESS_TERRITORY_WEIGHT: f32 = 0.5;
+const MAX_WEIGHT: i32 = 1000;
+
+#[derive(Deserialize)]
+pub struct TuningConfig {
+    // The candidate plays with this entrant's difficulty and personality, its scores are the starting point.
+    pub candidate: Entrant,
+    pub opponents: Vec<Entrant>,
+    pub population: usize,
+    pub generations: usize,
+    pub games_per_evaluation: usize,
+    pub max_rounds: usize,
+    #[serde(default)]
+    pub seed: u64,
+    pub maps: Vec<MapSpec>,
+    pub victory_condition: VictoryCondition,
+    #[serde(default)]
+    pub fog_of_war: bool,
+    // Candidates carried over unchanged to the next generation.
+    pub elite: usize,
+    // Chance for every weight to mutate, and the largest relative change.
+    pub mutation_rate: f32,
+    pub mutation_strength: f32,
+    // Where the best scores are saved after every generation.
+    pub output: String,
+}
+
+struct Candidate {
+    weights: [i32; Scores::COUNT],
+    fitness: f32,
+}
+
+/// Reads the config, evolves the scores and saves the best ones.
+pub fn run(config_path: &str) {
+    let f = File::open(config_path).expect("file should open read only");
+    let config: TuningConfig = serde_json::from_reader(f).expect("file should be proper JSON");
+    evolve(&config);
+}
+
+pub fn evolve(config: &TuningConfig) -> Scores {
+    // Kept apart from crate::rng, which every game reseeds
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let initial = config.candidate.ai().scores.to_weights();
+
+    let mut population: Vec<[i32; Scores::COUNT]> = vec![initial];
+    while population.len() < config.population.max(1) {
+        population.push(mutate(&initial, config, &mut rng));
+    }
+
+    if let Some(directory) = Path::new(&config.output).parent() {
+        std::fs::create_dir_all(directory).expect("output directory should be creatable");
+    }
+    let mut best = Candidate{weights: initial, fitness: f32::MIN};
+    for generation in 0..config.generations {
+        // Every candidate of a generation plays the same seeds, so they are compared on equal terms
+        let seed = config.seed + (generation * config.games_per_evaluation) as u64;
+        let mut candidates: Vec<Candidate> = population.iter().map(|weights| {
+            Candidate{weights: *weights, fitness: evaluate(weights, seed, config)}
+        }).collect();
+        candidates.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+
+        println!("Generation {}/{}: best fitness {:.3} with {:?}", generation + 1, config.generations, candidates[0].fitness, Scores::from_weights(candidates[0].weights));
+        if candidates[0].fitness > best.fitness {
+            best = Candidate{weights: candidates[0].weights, fitness: candidates[0].fitness};
+        }
+        Scores::from_weights(best.weights).to_json(&config.output);
+
+        let mut next: Vec<[i32; Scores::COUNT]> = candidates.iter().take(config.elite.max(1)).map(|c| c.weights).collect();
+        while next.len() < population.len() {
+            let a = select(&candidates, &mut rng);
+            let b = select(&candidates, &mut rng);
+            let child = crossover(a, b, &mut rng);
+            next.push(mutate(&child, config, &mut rng));
+        }
+        population = next;
+    }
+    Scores::from_weights(best.weights)
+}
+
+/// Plays the candidate against the opponents. Winning counts the most,
+/// but the land held at the end separates candidates that did not win.
+fn evaluate(weights: &[i32; Scores::COUNT], seed: u64, config: &TuningConfig) -> f32 {
+    let mut candidate = config.candidate.clone();
+    candidate.scores = Some(Scores::from_weights(*weights));
+    candidate.scores_file = None;
+    let mut entrants = vec![candidate];
+    entrants.extend(config.opponents.iter().cloned());
+
+    let tournament = TournamentConfig{
+        entrants,
+        players_per_game: None,
+        games: config.games_per_evaluation,
+        max_rounds: config.max_rounds,
+        seed,
+        maps: config.maps.clone(),
+        victory_condition: config.victory_condition.clone(),
+        fog_of_war: config.fog_of_war,
+        output: String::new(),
+    };
+    let results = tournament::play_tournament(&tournament);
+    let fitness: f32 = results.iter().map(candidate_fitness).sum();
+    fitness / results.len().max(1) as f32
+}
+
+fn candidate_fitness(result: &GameResult) -> f32 {
+    let seat = result.seats.iter().position(|&entrant| entrant == 0).unwrap();
+    let won = if result.winners.contains(&seat) {1.} else {0.};
+    let territory = result.territory.last().map_or(0., |shares| shares[seat]);
+    FITNESS_WIN_WEIGHT * won + FITNESS_TERRITORY_WEIGHT * territory
+}
+
+/// Tournament selection: the fitter of two random candidates.
+fn select<'a>(candidates: &'a [Candidate], rng: &mut StdRng) -> &'a [i32; Scores::COUNT] {
+    let a = &candidates[rng.gen_range(0..candidates.len())];
+    let b = &candidates[rng.gen_range(0..candidates.len())];
+    if a.fitness >= b.fitness {&a.weights} else {&b.weights}
+}
+
+/// Uniform crossover: every weight comes from either parent.
+fn crossover(a: &[i32; Scores::COUNT], b: &[i32; Scores::COUNT], rng: &mut StdRng) -> [i32; Scores::COUNT] {
+    let mut child = *a;
+    for (weight, other) in child.iter_mut().zip(b) {
+        if rng.gen::<bool>() {*weight = *other}
+    }
+    child
+}
+
+/// Nudges some of the weights by up to mutation_strength of their value, and at least by one.
+fn mutate(weights: &[i32; Scores::COUNT], config: &TuningConfig, rng: &mut StdRng) -> [i32; Scores::COUNT] {
+    let mut mutated = *weights;
+    for weight in mutated.iter_mut() {
+        if rng.gen::<f32>() < config.mutation_rate {
+            let max_change = ((*weight as f32 * config.mutation_strength).round() as i32).max(1);
+            *weight = (*weight + rng.gen_range(-max_change..=max_change)).clamp(0, MAX_WEIGHT);
+        }
+    }
+    mutated
+}
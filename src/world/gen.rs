@@ -1,7 +1,6 @@
 /// The worldgen module contains random world generation functions.
-/// It exports the generate_world() function for the Game class __init__()
-/// method. In the future it will be used by some other module that will
-/// allow the user to tweak the worldgen settings from within the game.
+/// It exports the generate() function for Game::init_world(), and the
+/// WorldSettings tweaked by the settings screen of the setup module.
 
 // import data
 // from playergen import Player
@@ -17,12 +16,10 @@ use crate::TileCategory;
 use crate::Cube;
 use crate::Locality;
 use crate::LocalityCategory;
-use crate::cubic::DIRECTIONS;
 use crate::cubic::Layout;
 use crate::cubic::OrientationKind;
 use crate::cubic::Pixel;
 use crate::cubic::pixel_to_cube;
-use crate::river::CubeSide;
 use super::extend_borders;
 // use crate::cubic::Layout;
 // use crate::cubic::POINTY;
@@ -33,50 +30,240 @@ use macroquad::shapes::draw_hexagon;
 use macroquad::shapes::draw_line;
 use macroquad::shapes::draw_poly_lines;
 use rand::Rng;
-use rand::random;
+use serde::Deserialize;
+use serde::Serialize;
+use strum::EnumIter;
+use rand::seq::SliceRandom;
 use rand::seq::index::sample;
 
+use crate::rng;
+use crate::rng::random;
+
 // #layout = cubic.Layout(cubic.orientation_pointy, cubic.Point(50, 50), cubic.Point(800, 550))
 // let layout = Layout(POINTY, (.02, .02), (.2, 0));
 // #layout = cubic.Layout(cubic.orientation_pointy, cubic.Point(1, 1), cubic.Point(0, 0))
 
+// A capital needs at least two thirds of land and this many other localities within this radius.
+const CAPITAL_SURROUNDINGS_RADIUS: usize = 2;
+const MIN_LOCALITIES_AROUND_CAPITAL: usize = 1;
+const MAXDIST_IMPROVEMENT_ROUNDS: usize = 10;
+// Edge distance of MaxDist capitals chosen on the settings screen.
+const MAXDIST_EDGE_DISTANCE: i32 = 2;
+// Chance for rivers generated from the settings screen to meander.
+const RIVER_MEANDER: f32 = 0.3;
+
 pub enum CapitalsGen {
     Classic,
-    Random,
-    MaxDist,
+    Random(BalanceSettings),
+    // Hands the capitals placed by the localities, e.g. by LocalitiesGen::Real, to the players.
+    Existing,
+    // Keeps capitals at least edge_distance tiles away from the edge of the map.
+    MaxDist{edge_distance: i32},
 }
 
 pub enum LocalitiesGen {
+    Random(LocalitySettings),
+    RandomOts(LocalitySettings), // One Tile of Space
+    // Real cities at their positions on a ShapeGen::Custom map, the most populous being the capitals.
+    Real(Vec<RealCity>),
+}
+
+// Real cities at least this populous, but not among the capitals, become satellite capitals.
+const SATELLITE_CAPITAL_POPULATION: u64 = 1_000_000;
+// Scale of the Mercator projection of custom shapes and real cities, the earth's radius in metres at 1:750.
+pub const MERCATOR_SCALE: f32 = 6371000.0 / 750.;
+
+/// A city of assets/cities.json, positioned in the projected coordinates of custom shapes, see mercator().
+#[derive(Clone, Debug)]
+pub struct RealCity {
+    pub name: String,
+    pub population: u64,
+    pub position: (f32, f32),
+}
+
+/// Projects a longitude and latitude in degrees the way custom shapes are, y growing southwards.
+pub fn mercator(longitude: f32, latitude: f32) -> (f32, f32) {
+    let x = MERCATOR_SCALE * longitude.to_radians();
+    let y = MERCATOR_SCALE * (std::f32::consts::FRAC_PI_4 + latitude.to_radians() / 2.).tan().ln();
+    (x, -y)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LocalitySettings {
+    // Localities per tile. Random counts every tile, RandomOts only land.
+    pub density: f32,
+    // Chance for a locality next to water to be a port city.
+    pub port_chance: f32,
+    // Chance for a locality to be an airport instead.
+    pub airport_share: f32,
+}
+
+impl Default for LocalitySettings {
+    fn default() -> Self {
+        LocalitySettings{density: 0.1, port_chance: 0.5, airport_share: 0.1}
+    }
+}
+
+/// Constraints on random capitals, so that no player starts worse off than the others.
+#[derive(Clone, Copy, Debug)]
+pub struct BalanceSettings {
+    // Every capital gets the same number of localities within this radius.
+    pub radius: usize,
+    // Smallest number of steps over land between two capitals, which must all be connected by land.
+    pub min_distance: i32,
+    // Random draws of the capitals, the best one is then evened out.
+    pub attempts: usize,
+}
+
+impl Default for BalanceSettings {
+    fn default() -> Self {
+        BalanceSettings{radius: CAPITAL_SURROUNDINGS_RADIUS, min_distance: 4, attempts: 50}
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum ShapeKind {
+    Hexagonal,
+    Classic,
+    // The custom shape of WorldSettings, e.g. the outline of a country.
+    Custom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum LocalitiesKind {
     Random,
-    RandomOts, // One Tile of Space
+    // One tile of space between localities.
+    Spaced,
+    // The real cities of WorldSettings at their positions, only on custom shapes. The biggest are the capitals.
+    Real,
 }
 
-pub enum ShapeGen {
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum CapitalsKind {
+    Random,
     Classic,
+    MaxDist,
+}
+
+/// Everything the settings screen can tweak, turned into the generators above by World::generate_with_settings().
+#[derive(Clone, Debug)]
+pub struct WorldSettings {
+    pub shape: ShapeKind,
+    // Radius of a hexagonal world. Classic boards are 2 * size + 4 columns by size + 3 rows, 20x11 for 8. Custom shapes keep their own size.
+    pub size: i32,
+    // All land when None.
+    pub terrain: Option<TerrainPreset>,
+    pub water_ratio: f32,
+    pub rivers: usize,
+    pub locality_placement: LocalitiesKind,
+    pub localities: LocalitySettings,
+    pub capitals: CapitalsKind,
+    pub balance: BalanceSettings,
+    pub seed: u64,
+    // Projected like mercator(), see ShapeGen::Custom.
+    pub custom_shape: Vec<(f32, f32)>,
+    pub real_cities: Vec<RealCity>,
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        WorldSettings{
+            shape: ShapeKind::Hexagonal,
+            size: 8,
+            terrain: None,
+            water_ratio: 0.3,
+            rivers: 0,
+            locality_placement: LocalitiesKind::Random,
+            localities: LocalitySettings::default(),
+            capitals: CapitalsKind::Random,
+            balance: BalanceSettings::default(),
+            seed: 0,
+            custom_shape: vec!(),
+            real_cities: vec!(),
+        }
+    }
+}
+
+pub enum ShapeGen {
+    // Width and height of a rectangular board.
+    Classic(i32, i32),
     Hexagonal(i32),
     Custom(Vec<(f32, f32)>),
 }
+/// Which tiles of the shape are water, the float being the share of water tiles.
+pub enum TerrainGen {
+    Land,
+    Noise(TerrainPreset, f32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum TerrainPreset {
+    // A few continents with lakes and inlets.
+    Continents,
+    // One landmass in the middle of the ocean.
+    Pangaea,
+    // Many small islands.
+    Archipelago,
+    // Land around a sea in the middle.
+    InlandSea,
+}
+
+// Octaves of value noise summed into the elevation, each at twice the frequency and half the amplitude.
+const NOISE_OCTAVES: usize = 4;
+// Features of the first octave span roughly this many tiles.
+const NOISE_SCALE: f32 = 8.;
+
 pub enum RiverGen {
+    // Number of rivers and their chance to meander.
     Random(usize, f32),
-    Custom(Vec<(usize, f32, f32)>),
 }
 
 impl World {
-    /// Populates and returns new World instance with cubes, resulting in a
-    /// map identical in shape and size to the original hex empire 1 world map.
-    fn gen_water(&mut self) {
-        self.iter_mut().for_each(|(_, tile)| {
-            if random::<f32>() > 0.7 {
-                tile.category = TileCategory::Water
-            }
-        })
+    /// Floods the lowest tiles of a fractal value noise elevation, biased by the distance
+    /// from the middle of the map according to the preset.
+    fn gen_noise_terrain(&mut self, preset: TerrainPreset, water_ratio: f32) {
+        if self.is_empty() {return}
+        let seed: u32 = random();
+        let center_q = self.keys().map(|c| c.q() as f32).sum::<f32>() / self.len() as f32;
+        let center_r = self.keys().map(|c| c.r() as f32).sum::<f32>() / self.len() as f32;
+        let distance_from_center = |cube: &Cube<i32>| {
+            let (dq, dr) = (cube.q() as f32 - center_q, cube.r() as f32 - center_r);
+            dq.abs().max(dr.abs()).max((dq + dr).abs())
+        };
+        let max_distance = self.keys().map(distance_from_center).fold(1., f32::max);
+
+        let (scale, falloff) = match preset {
+            TerrainPreset::Continents => (NOISE_SCALE, 0.),
+            TerrainPreset::Pangaea => (NOISE_SCALE, -1.),
+            TerrainPreset::Archipelago => (NOISE_SCALE / 3., -0.2),
+            TerrainPreset::InlandSea => (NOISE_SCALE, 1.),
+        };
+        // Sorted, so that ties are flooded in the same order for the same seed
+        let mut elevations: Vec<(Cube<i32>, f32)> = self.keys().map(|cube| {
+            let x = cube.q() as f32 * 1.5;
+            let y = 3f32.sqrt() * (cube.r() as f32 + cube.q() as f32 / 2.);
+            let elevation = fractal_noise(x / scale, y / scale, seed) + falloff * distance_from_center(cube) / max_distance;
+            (*cube, elevation)
+        }).collect();
+        elevations.sort_by(|(c1, e1), (c2, e2)| e1.total_cmp(e2).then(c1.cmp(c2)));
+
+        let water_count = (elevations.len() as f32 * water_ratio.clamp(0., 1.)).round() as usize;
+        for (cube, _) in elevations.iter().take(water_count) {
+            self.get_mut(cube).unwrap().category = TileCategory::Water;
+        }
     }
 
-    fn gen_classic_shape(&mut self) {
+    fn choose_terrain_gen(&mut self, terrain: TerrainGen) {
+        match terrain {
+            TerrainGen::Land => (),
+            TerrainGen::Noise(preset, water_ratio) => self.gen_noise_terrain(preset, water_ratio),
+        }
+    }
+
+    /// A rectangular board of width columns and height rows, the original hex empire 1 board being 20x11.
+    fn gen_classic_shape(&mut self, width: i32, height: i32) {
         // layout.orientation = FLAT
         // layout.origin = (-10.0, 10.0)
-        let width = 20;
-        let height = 11;
         for q in 0..width {
             let q_offset = q >> 1;
             for r in (-1 * q_offset)..(height - q_offset) {
@@ -99,7 +286,7 @@ impl World {
 
     fn choose_shape_gen(&mut self, shape: ShapeGen, init_layout: &crate::cubic::Layout<f32>) {
         match shape {
-            ShapeGen::Classic => self.gen_classic_shape(),
+            ShapeGen::Classic(width, height) => self.gen_classic_shape(width, height),
             ShapeGen::Hexagonal(radius) => self.gen_hexagonal_shape(radius),
             ShapeGen::Custom(shape) => {
                 let my_shape_map = World::from_shape(shape, init_layout);
@@ -108,280 +295,183 @@ impl World {
         }
     }
 
-    fn choose_river_gen(&mut self, river: RiverGen, init_layout: &Layout<f32>) {
-        let land_tiles: HashSet<&Cube<i32>> = self.world.iter().filter_map(|(c, t)| {
-            if matches!(t.category, TileCategory::Farmland) {Some(c)} else {None}
-        }).collect();
+    fn choose_river_gen(&mut self, river: RiverGen) {
         match river {
-            RiverGen::Random(ln, th) => {
-                self.rivers = crate::river::generate_river(land_tiles, ln, th);
+            RiverGen::Random(count, meander) => {
+                self.rivers = crate::river::generate_rivers(self, count, meander);
             }
-            RiverGen::Custom(mut river) => {
-                // TODO new algo:
-                // iter line by line
-                // round to find all cubes
-                // draw some line along the cube path
-
-                // let x_min = river.iter().fold(f32::NAN, |a, &b| a.min(b.1));
-                // let y_min = river.iter().fold(f32::NAN, |a, &b| a.min(b.2));
-                // river = river.iter().map(|(idx, x, y)| (*idx, x - x_min, y - y_min)).collect();
-
-                // 1. construct cube paths
-                let mut rivers_sets: HashMap<usize, HashMap<Cube<i32>, usize>> = HashMap::new();
-                // let mut river_line: HashMap<Cube<i32>, usize> = HashMap::new();
-                for (cube_idx, (id, x, y)) in river.iter().enumerate() {
-                    let cube = pixel_to_cube(&init_layout, [*x, *y]);
-                    let origin: Cube<i32> = cube.round();
-                    rivers_sets.entry(*id).or_insert(HashMap::new()).insert(origin, cube_idx);
-                    // river_line.insert(origin, cube_idx);
-                }
-
-                // 2. sort cube paths by insertion order
-                let mut rivers: Vec<Vec<Cube<i32>>> = Vec::new();
-                for river_line in rivers_sets.values_mut() {
-                    let mut line: Vec<(&Cube<i32>, &usize)> = river_line.iter().collect();
-                    line.sort_by(|(c1, i1), (c2, i2)| i1.cmp(i2));
-                    let line: Vec<Cube<i32>> = line.iter().map(|(c, i)| **c).collect();
-                    rivers.push(line);
-                }
-
-                // 3. construct segment lines
-                for river_line in rivers {
-                    println!("river_line: {:?}", river_line);
-                    // let mut prev_cube = river_line[0];
-                    let mut rivers_segments: Vec<CubeSide> = Vec::new();
-                    let mut origin_cube = Cube::new(0,0); // initialised to satisfy borrowck
-                    for idx in 0..(river_line.len() - 1) {
-                        // let idx_plus_one = if idx < river_line.len() {idx + 1} else {idx};
-                        let cube = river_line[idx];
-                        let next_cube = river_line[idx + 1];
-                        let dir = next_cube - cube;
-                        println!("cube: {:}, next_cube: {:}, dir: {:}", cube, next_cube, dir);
-                        let dir_idx = DIRECTIONS.iter().position(|c| c == &dir).unwrap_or_else(|| 0);
-                        let mut dir_iter = DIRECTIONS.iter().cycle().skip(dir_idx);
-
-                        if idx == 0 {
-                            origin_cube = cube;
-                            let start_dir = dir_iter.next().unwrap();
-                            rivers_segments.push(CubeSide::from(Cube::<f32>::from(cube) + (*start_dir/2)));
-                            // let start_dir = DIRECTIONS[(dir_idx + 1) % 6];
-                            // rivers_segments.push(CubeSide::from(Cube::<f32>::from(cube) + (start_dir/2)));
-                        }
-
-                        // let start_seg_dir = Cube::<i32>::from((Cube::<f32>::from(rivers_segments.last().unwrap()) - Cube::<f32>::from(cube)) * 2.);
-                        // let seg_dir = start_seg_dir
-                        // while this_seg_dir != start_seg_dir {
-                        //     let value = CubeSide::from(Cube::<f32>::from(cube) + (*this_seg_dir / 2));
-                        //     rivers_segments.push(value);
-                        // }
-
-                        let mut seg_dir_idx = (dir_idx + 1) % 6;
-                        // let mut prev_cube = river_line[max(0, (idx as i32 - 1)) as usize];
-                        while seg_dir_idx != dir_idx {
-                            let segment = rivers_segments.last().unwrap();
-                            // if idx > 0 {prev_cube = river_line[idx - 1]}
-
-                            // println!("segment: {:}, cube: {:}, origin_cube: {:}", segment, cube, origin_cube);
-                            // println!("segment - cube: {:}", *segment - cube);
-                            // println!("(segment - cube) * 2: {:}", (*segment - origin_cube) * 2);
-                            // let seg_dir = ((*segment - origin_cube) * 2).int();
-                            let seg_dir: Cube<i32> = ((Cube::<f32>::from(*segment) - Cube::<f32>::from(origin_cube)) * 2.).round();
-
-
-                            // let seg_dir: Cube<i32> = (Cube::<f32>::from(*segment - prev_cube) * 2.).round();
-                            println!("seg_dir: {:}", seg_dir);
-                            seg_dir_idx = DIRECTIONS.iter().position(|c| c == &seg_dir).unwrap();
-                            let next_dir = DIRECTIONS.iter().cycle().skip((seg_dir_idx + 1)).next().unwrap();
-                            println!("next_dir: {:}",next_dir);
-                            let value = CubeSide::from(Cube::<f32>::from(cube) + (*next_dir / 2));
-                            println!("value: {:}", value);
-                            rivers_segments.push(value);
-                            origin_cube = cube;
-                            // print!("rs: {:?}", rivers_segments);
-                            // self.rivers.insert()
-                        }
-
+        }
+    }
 
-                        // let mut seg_dir_idx = (dir_idx + 1) % 6;//(dir_idx + 1) % 6;
-                        // let mut prev_cube = river_line[max(0, (idx as i32 - 1)) as usize];
-                        // while seg_dir_idx != dir_idx {
-                        //     let segment = rivers_segments.last().unwrap();
-                        //     if idx > 0 {prev_cube = river_line[idx - 1]}
-                        //     let seg_dir = ((*segment - prev_cube) * 2).int();
-                        //     // let seg_dir: Cube<i32> = (Cube::<f32>::from(*segment - prev_cube) * 2.).round();
-                        //     println!("seg_dir: {:}",seg_dir);
-                        //     seg_dir_idx = DIRECTIONS.iter().position(|c| c == &seg_dir).unwrap();
-                        //     let next_dir = DIRECTIONS.iter().cycle().skip(seg_dir_idx + 1).next().unwrap();
-                        //     println!("next_dir: {:}",next_dir);
-                        //     let value = CubeSide::from(Cube::<f32>::from(cube) + (*next_dir / 2));
-                        //     rivers_segments.push(value);
-                        //     // self.rivers.insert()
-                        // }
+    /// Picks the category of a new locality: a port city next to water, sometimes an airport, otherwise a city.
+    fn roll_locality_category(&self, cube: &Cube<i32>, settings: &LocalitySettings) -> LocalityCategory {
+        let is_coastal = cube.disc(1).iter().any(|n| self.get(n).is_some_and(|t| matches!(t.category, TileCategory::Water)));
+        if random::<f32>() < settings.airport_share {
+            LocalityCategory::Airport
+        } else if is_coastal && random::<f32>() < settings.port_chance {
+            LocalityCategory::PortCity
+        } else {
+            LocalityCategory::City
+        }
+    }
 
-                    }
-                    self.rivers.extend(rivers_segments);
-                }
+    /// Removed and reinserted to keep the airport cache up to date.
+    fn place_locality(&mut self, cube: &Cube<i32>, category: LocalityCategory, locality_names: &mut Vec<&str>) {
+        let mut tile = self.remove(cube).unwrap();
+        tile.locality = Some(Locality::new(locality_names.pop().unwrap_or(&"city"), category));
+        self.insert(*cube, tile);
+    }
 
-                // let mut counts: HashMap<CubeSide, usize> = HashMap::new();
-                // self.rivers = HashSet::new();
-
-                // let x_min = river.iter().fold(f32::NAN, |a, &b| a.min(b.0));
-                // let y_min = river.iter().fold(f32::NAN, |a, &b| a.min(b.1));
-                // let river_2: Vec<_> = river.iter().map(|(x, y)| (x - x_min, y - y_min)).collect();
-
-                // let (mut x_factor, mut y_factor) = (1.5, f32::sqrt(3.));
-                // if matches!(init_layout.orientation, OrientationKind::Pointy(_)) {
-                //     (x_factor, y_factor) = (y_factor, x_factor);
-                // }
-                // for (mut x, mut y) in river_2 {
-                //     // x = x / (init_layout.size[0] * x_factor);
-                //     // y = y / (init_layout.size[1] * y_factor);
-                //     let cube = pixel_to_cube(&init_layout, [x, y]);
-                //     let origin: Cube<i32> = cube.round();
-
-                //     let corners = Cube::<f32>::from(origin).corners(&init_layout);
-                //     let mut corners_2 = corners.clone();
-                //     corners_2.rotate_left(1);
-                //     let segments: Vec<_> = corners.iter().zip(corners_2.iter()).collect();
-                //     let midpoints: Vec<_> = segments.iter().map(|(p1 , p2)| (**p1 + **p2) / 2.).collect();
-                //     let diffs: Vec<_> = midpoints.iter().map(|p| (x - p.0).powf(2.) + (y - p.1).powf(2.)).collect();
-
-                //     let idx = diffs.iter()
-                //         .enumerate()
-                //         .max_by(|(_, a), (_, b)| a.total_cmp(b))
-                //         .map(|(index, _)| index)
-                //         .unwrap();
-
-                //     let dir = DIRECTIONS[idx];
-                //     let segment = CubeSide::from(Cube::<f32>::from(origin) + dir/2);
-                //     let count = counts.entry(segment).or_insert(0);
-                //     *count += 1;
-                //     // self.rivers.insert(segment);
-
-                // }
-                // self.rivers = counts.iter().filter_map(|(k, v)| if *v > 0 {Some(*k)} else {None}).collect();
-                println!("rivers: {:?}", self.rivers);
+    fn gen_random_localities(&mut self, locality_names: &mut Vec<&str>, settings: &LocalitySettings) {
+        // Sorted, so that the same seed always picks the same cubes
+        let mut cubes: Vec<Cube<i32>> = self.keys().copied().collect();
+        cubes.sort();
+        let amount = ((self.len() as f32 * settings.density) as usize).min(cubes.len());
+        let random_positions = rng::with_rng(|rng| sample(rng, cubes.len(), amount));
+
+        for world_index in random_positions.into_iter() {
+            let cube = cubes[world_index];
+            if !matches!(self[&cube].category, TileCategory::Water) {
+                let category = self.roll_locality_category(&cube, settings);
+                self.place_locality(&cube, category, locality_names);
             }
         }
     }
 
-    fn gen_random_localities(&mut self, locality_names: &mut Vec<&str>) {
-        let mut rng = rand::thread_rng();
-        let amount = self.len() / 10;
-        let random_positions = sample(&mut rng, self.len(), amount);
-        // let mut categories: Vec<LocalityCategory> = Vec::new();
-
-        for (i, world_index) in random_positions.into_iter().enumerate() {
-            let cube = self.keys().skip(world_index).next().unwrap();
-            let neighbours = cube.disc(1);
-            let mut category = LocalityCategory::City;
-            for n in neighbours {
-                // let mut category: LocalityCategory = LocalityCategory::City;
-                if self.get(&n).is_some_and(|t| matches!(t.category, TileCategory::Water)) {
-                    // 50% chance to turn city into portcity
-                    let roll = rng.gen::<f32>();
-                    if roll > 0.5 {
-                        category = LocalityCategory::PortCity;
-                    }
-                    
-                    // categories.push(category);
-                    break;
-                }
-                // categories.push(category);
-            }
-
-            // 10% chance to turn into airport
-            let roll = rng.gen::<f32>();
-            if roll > 0.9 {
-                category = LocalityCategory::Airport;
+    /// Same as gen_random_localities(), but ensures there is one tile of space between every locality.
+    /// Dart throwing over the shuffled land: a cube is taken unless one of its neighbours already is,
+    /// until the density is reached or no free cube is left.
+    fn gen_random_localities_with_ots(&mut self, locality_names: &mut Vec<&str>, settings: &LocalitySettings) {
+        let mut land: Vec<Cube<i32>> = self.iter()
+            .filter(|(_, tile)| !matches!(tile.category, TileCategory::Water))
+            .map(|(cube, _)| *cube)
+            .collect();
+        land.sort();
+        rng::with_rng(|rng| land.shuffle(rng));
+        let amount = (land.len() as f32 * settings.density) as usize;
+
+        let mut placed = 0;
+        for cube in land {
+            if placed >= amount {break}
+            let is_free = cube.disc(1).iter().all(|n| self.get(n).map_or(true, |t| t.locality.is_none()));
+            if is_free {
+                let category = self.roll_locality_category(&cube, settings);
+                self.place_locality(&cube, category, locality_names);
+                placed += 1;
             }
+        }
+        if placed < amount {
+            println!("Only {} of {} localities fit with one tile of space between them.", placed, amount);
+        }
+    }
 
-            let tile = self.values_mut().skip(world_index).next().unwrap();
-            if !matches!(tile.category, TileCategory::Water) {
-                tile.locality = Some(Locality::new(locality_names.pop().unwrap_or(&"city"), category))//categories.remove(i)))
+    /// Places the cities on the tiles at their real positions, the biggest first, skipping those in the water or
+    /// sharing a tile with a bigger one. The biggest become the capitals, then the satellite capitals, and the
+    /// remaining cities on the coast become port cities.
+    fn gen_real_localities(&mut self, mut cities: Vec<RealCity>, capitals: usize, shape_origin: (f32, f32), init_layout: &Layout<f32>) {
+        if cities.is_empty() {
+            println!("No real cities to place, assets/cities.json needs their latitude and longitude.");
+            return
+        }
+        cities.sort_by(|a, b| b.population.cmp(&a.population).then(a.name.cmp(&b.name)));
+        let mut placed_capitals = 0;
+        let mut outside = vec!();
+        let mut taken = vec!();
+        for city in cities {
+            let pixel = [city.position.0 - shape_origin.0, city.position.1 - shape_origin.1];
+            let cube: Cube<i32> = pixel_to_cube(init_layout, pixel).round();
+            match self.get(&cube) {
+                None => {outside.push(city.name); continue}
+                Some(t) if matches!(t.category, TileCategory::Water) || t.locality.is_some() => {taken.push(city.name); continue}
+                Some(_) => (),
             }
+            let is_coastal = cube.disc(1).iter().any(|n| self.get(n).is_some_and(|t| matches!(t.category, TileCategory::Water)));
+            let category = if placed_capitals < capitals {
+                placed_capitals += 1;
+                LocalityCategory::Capital
+            } else if city.population >= SATELLITE_CAPITAL_POPULATION {
+                LocalityCategory::SatelliteCapital
+            } else if is_coastal {
+                LocalityCategory::PortCity
+            } else {
+                LocalityCategory::City
+            };
+            let mut tile = self.remove(&cube).unwrap();
+            tile.locality = Some(Locality::new(&city.name, category));
+            self.insert(cube, tile);
+        }
+        if !outside.is_empty() {
+            println!("{} real cities fall outside the shape: {}", outside.len(), outside.join(", "));
+        }
+        if !taken.is_empty() {
+            println!("{} real cities fall on water or on another city: {}", taken.len(), taken.join(", "));
         }
-
-        // for (world_index, locality_name) in random_positions.into_iter().zip(locality_names.into_iter().cycle()) {
-        //     let tile = self.values_mut().skip(world_index).next().unwrap();
-        //     tile.category = categories[i]
-        // }
-
-        // for ((cube, tile), locality_name) in self.iter().zip(locality_names.into_iter().cycle()) {
-        //     if !matches!(tile.category, TileCategory::Water) && rand::thread_rng().gen::<f32>() > 0.9 {
-        //         // check for an adjacent water tile
-        //         let mut category = LocalityCategory::City;
-        //         for neighbour in cube.disc(1) {
-        //             if self.get(&neighbour).is_some_and(|tile| matches!(tile.category, TileCategory::Water)) {
-        //                 category = LocalityCategory::PortCity;
-        //                 break;
-        //             }
-        //         }
-        //         tile.locality = Some(Locality::new(locality_name, category))
-        //     }
-        // }
     }
 
-    fn gen_random_localities_with_ots(&mut self, locality_names: &mut Vec<&str>) {
-        unimplemented!();
-    }
-    /// Same as localgen_random(), but ensures there is one tile of space between every locality.
-    // get bounds, calc legal cubes, 
-    // fn gen_random_localities_with_ots(&mut self, locality_names: Vec<&str>) {
-    //     for (cube, locality_name) in self.keys().zip(locality_names) {
-    //         let mut tile = self.remove(cube).unwrap();
-    //         let mut flag = true;
-    //         if tile.locality.is_some() {
-    //             flag = false;
-    //         }
-    //         for neighbour in cube.disc(1) {
-    //             if let Some(tile) = self.get(&neighbour) {
-    //                 if tile.locality.is_some() {
-    //                     flag = false;
-    //                     break;
-    //                 }
-    //             }
-    //         }
-    //         if flag && rand::thread_rng().gen::<f32>() > 0.9 {
-    //             tile.locality = Some(Locality::new(locality_name, LocalityCategory::City));
-    //         }
-    //     }
-    // }
-
-    fn choose_localities_gen(&mut self, gen: LocalitiesGen, locality_names: &mut Vec<&str>) {
+    fn choose_localities_gen(&mut self, gen: LocalitiesGen, locality_names: &mut Vec<&str>, capitals: usize, shape_origin: (f32, f32), init_layout: &Layout<f32>) {
         match gen {
-            LocalitiesGen::Random => self.gen_random_localities(locality_names),
-            LocalitiesGen::RandomOts => self.gen_random_localities_with_ots(locality_names),
+            LocalitiesGen::Random(settings) => self.gen_random_localities(locality_names, &settings),
+            LocalitiesGen::RandomOts(settings) => self.gen_random_localities_with_ots(locality_names, &settings),
+            LocalitiesGen::Real(cities) => self.gen_real_localities(cities, capitals, shape_origin, init_layout),
         }
     }
 
-    /// Spawn positions hardcoded to correspond to the original hex empire 1 spawn positions.
-    fn gen_classic_capitals(&mut self, locality_names: &mut Vec<&str>, mut players: &mut Vec<Player>) {
-        let starting_positions = [
-            Cube::new(1, 1),
-            Cube::new(1, 9),
-            Cube::new(18, -8),
-            Cube::new(18, 0),
-        ];
-
-        for (index, ((player, pos), locality_name)) in players.iter_mut().zip(starting_positions.iter()).zip(locality_names.iter()).enumerate() {
-            let mut tile = self.get_mut(&pos).unwrap();
-            tile.category = TileCategory::Farmland;
-            tile.owner_index = Some(index);
-            tile.locality = Some(Locality::new(locality_name, LocalityCategory::Capital));
-            player.capital_pos = Some(*pos);
+    /// Spawn positions at the corners, edges and centre of a rectangular board, one tile in from the border.
+    /// On the 20x11 board with four players these are the original hex empire 1 spawn positions.
+    fn gen_classic_capitals(&mut self, locality_names: &mut Vec<&str>, players: &mut Vec<Player>) {
+        if self.is_empty() {return}
+        // Bounds of the board in offset coordinates, columns being q
+        let row = |cube: &Cube<i32>| cube.r() + (cube.q() >> 1);
+        let (left, right) = (self.keys().map(|c| c.q()).min().unwrap(), self.keys().map(|c| c.q()).max().unwrap());
+        let (top, bottom) = (self.keys().map(row).min().unwrap(), self.keys().map(row).max().unwrap());
+        let (left, right, top, bottom) = (left + 1, right - 1, top + 1, bottom - 1);
+        let (middle_col, middle_row) = ((left + right) / 2, (top + bottom) / 2);
+
+        let top_left = (left, top);
+        let top_right = (right, top);
+        let bottom_left = (left, bottom);
+        let bottom_right = (right, bottom);
+        let top_middle = (middle_col, top);
+        let bottom_middle = (middle_col, bottom);
+        let left_middle = (left, middle_row);
+        let right_middle = (right, middle_row);
+        let centre = (middle_col, middle_row);
+        // Every set is symmetric through the centre of the board, except for three players, who are
+        // only mirrored left to right. The localities around the capitals are evened out below.
+        let starting_positions: Vec<(i32, i32)> = match players.len() {
+            0 => vec!(),
+            1 => vec!(top_left),
+            2 => vec!(top_left, bottom_right),
+            3 => vec!(top_left, top_right, bottom_middle),
+            4 => vec!(top_left, bottom_left, top_right, bottom_right),
+            5 => vec!(top_left, bottom_left, top_right, bottom_right, centre),
+            6 => vec!(top_left, bottom_left, top_right, bottom_right, top_middle, bottom_middle),
+            7 => vec!(top_left, bottom_left, top_right, bottom_right, top_middle, bottom_middle, centre),
+            _ => vec!(top_left, bottom_left, top_right, bottom_right, top_middle, bottom_middle, left_middle, right_middle),
+        };
+        if players.len() > starting_positions.len() {
+            println!("Classic capitals support up to {} players, {} will start without a capital.", starting_positions.len(), players.len() - starting_positions.len());
+        }
 
-            let set = self.cubes_by_ownership.entry(index).or_insert(HashSet::new());
-            set.insert(*pos);
+        let mut capitals = vec!();
+        for (index, (player, (col, row))) in players.iter_mut().zip(starting_positions).enumerate() {
+            let pos = Cube::new(col, row - (col >> 1));
+            if !self.contains_key(&pos) {continue}
+            player.capital_pos = Some(pos);
+            capitals.push(pos);
 
-            // extend_borders(self, &pos);
+            // The disc includes the capital. Removed and reinserted to keep the ownership and airport caches up to date
             for neighbour in pos.disc(1) {
-                if let Some(tile) = self.get_mut(&neighbour) {
-                    tile.category = TileCategory::Farmland;
-                    tile.owner_index = Some(index);
+                let Some(mut tile) = self.remove(&neighbour) else {continue};
+                tile.category = TileCategory::Farmland;
+                tile.owner_index = Some(index);
+                if neighbour == pos {
+                    tile.locality = Some(Locality::new(locality_names.pop().unwrap_or("capital"), LocalityCategory::Capital));
                 }
+                self.insert(neighbour, tile);
             }
         }
+        self.even_out_localities(&capitals, CAPITAL_SURROUNDINGS_RADIUS, locality_names);
     }
     // def gen_random_capitals(filled_world, players):
     //     for player in players:
@@ -404,35 +494,291 @@ impl World {
     //         set.insert(cube);
     //     });
     // }
-    /// pick a random city for each player and turn it into their capital
-    fn gen_random_capitals(&mut self, locality_names: &mut Vec<&str>, mut players: &mut Vec<Player>) {
-        let cubes_with_cities: HashSet<Cube<i32>> = self.iter().filter(|(c, t)| {
+    /// Picks a random city for each player and turns it into their capital. Of several draws, the one breaking
+    /// the fewest balance constraints and with the most even surroundings is kept, then evened out.
+    fn gen_random_capitals(&mut self, locality_names: &mut Vec<&str>, players: &mut Vec<Player>, balance: &BalanceSettings) {
+        if players.is_empty() {return}
+        let mut cubes_with_cities: Vec<Cube<i32>> = self.iter().filter(|(_, t)| {
             t.locality.as_ref().is_some_and(|l| {
                 matches!(l.category, LocalityCategory::City)
             })
-        }).map(|(c, t)| *c).collect();
+        }).map(|(c, _)| *c).collect();
+        cubes_with_cities.sort();
+        if cubes_with_cities.len() < players.len() {
+            // Too few localities were generated, e.g. at a low density, so cities are founded on free land
+            let mut free_land: Vec<Cube<i32>> = self.iter().filter(|(_, t)| {
+                t.locality.is_none() && !matches!(t.category, TileCategory::Water)
+            }).map(|(c, _)| *c).collect();
+            free_land.sort();
+            let missing = (players.len() - cubes_with_cities.len()).min(free_land.len());
+            for index in rng::with_rng(|rng| sample(rng, free_land.len(), missing)) {
+                self.place_locality(&free_land[index], LocalityCategory::City, locality_names);
+                cubes_with_cities.push(free_land[index]);
+            }
+            cubes_with_cities.sort();
+            println!("Founded {} cities, so that every player has a capital.", missing);
+        }
+        if cubes_with_cities.len() < players.len() {
+            println!("Not enough land for {} capitals, only {} players get one.", players.len(), cubes_with_cities.len());
+            if cubes_with_cities.is_empty() {return}
+        }
+        let capital_count = players.len().min(cubes_with_cities.len());
+
+        let mut best: Option<((usize, usize), Vec<Cube<i32>>)> = None;
+        for _ in 0..balance.attempts.max(1) {
+            let start_pos = rng::with_rng(|rng| sample(rng, cubes_with_cities.len(), capital_count));
+            let capitals: Vec<Cube<i32>> = start_pos.into_iter().map(|index| cubes_with_cities[index]).collect();
+            let counts = self.localities_around(&capitals, balance.radius);
+            let spread = counts.iter().max().unwrap() - counts.iter().min().unwrap();
+            let score = (self.broken_capital_constraints(&capitals, balance.min_distance), spread);
+            if best.as_ref().map_or(true, |(best_score, _)| score < *best_score) {
+                best = Some((score, capitals));
+            }
+            if score == (0, 0) {break}
+        }
+        let (_, capitals) = best.unwrap();
 
-        let start_pos = sample(&mut rand::thread_rng(), cubes_with_cities.len(), players.len());
-        players.iter_mut().enumerate().for_each(|(player_index, player)| {
-            let index = start_pos.index(player_index);
-            let cube = cubes_with_cities.iter().skip(index).next().unwrap().clone();
-            let tile = self.get_mut(&cube).unwrap();
+        for (player_index, (player, cube)) in players.iter_mut().zip(&capitals).enumerate() {
+            // Removed and reinserted to keep the ownership cache up to date
+            let mut tile = self.remove(cube).unwrap();
             tile.owner_index = Some(player_index);
             tile.locality.as_mut().unwrap().category = LocalityCategory::Capital;
+            self.insert(*cube, tile);
+            player.capital_pos = Some(*cube);
+        }
+        self.even_out_localities(&capitals, balance.radius, locality_names);
+        self.report_balance(&capitals, balance);
+    }
+
+    /// Number of steps from the start to every land cube, moving over land only.
+    fn land_distances(&self, start: &Cube<i32>) -> HashMap<Cube<i32>, i32> {
+        let is_land = |cube: &Cube<i32>| self.get(cube).is_some_and(|tile| !matches!(tile.category, TileCategory::Water));
+        let mut distances = HashMap::from([(*start, 0)]);
+        let mut frontier = std::collections::VecDeque::from([*start]);
+        while let Some(current) = frontier.pop_front() {
+            let distance = distances[&current];
+            for neighbour in current.disc(1) {
+                if is_land(&neighbour) && !distances.contains_key(&neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    frontier.push_back(neighbour);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Localities other than the capitals within the radius of every capital.
+    fn localities_around(&self, capitals: &[Cube<i32>], radius: usize) -> Vec<usize> {
+        capitals.iter().map(|capital| {
+            capital.disc(radius).iter().filter(|cube| {
+                !capitals.contains(cube) && self.get(cube).is_some_and(|tile| tile.locality.is_some())
+            }).count()
+        }).collect()
+    }
+
+    /// Steps over land between every pair of capitals, None when water parts them.
+    fn capital_distances(&self, capitals: &[Cube<i32>]) -> Vec<Option<i32>> {
+        capitals.iter().enumerate().flat_map(|(index, capital)| {
+            let distances = self.land_distances(capital);
+            capitals[index + 1..].iter().map(move |other| distances.get(other).copied())
+        }).collect()
+    }
+
+    /// Pairs of capitals parted by water or closer than the minimum distance.
+    fn broken_capital_constraints(&self, capitals: &[Cube<i32>], min_distance: i32) -> usize {
+        self.capital_distances(capitals).iter().filter(|distance| distance.map_or(true, |d| d < min_distance)).count()
+    }
+
+    /// Adds localities around the capital with the fewest, or when it has no room left, removes them around
+    /// the capital with the most, until every capital has the same number within the radius.
+    /// Only tiles out of reach of the other capitals are changed, so that no other count moves.
+    fn even_out_localities(&mut self, capitals: &[Cube<i32>], radius: usize, locality_names: &mut Vec<&str>) {
+        let reach_only = |capital: &Cube<i32>| -> Vec<Cube<i32>> {
+            let mut cubes: Vec<Cube<i32>> = capital.disc(radius).into_iter().filter(|cube| {
+                !capitals.contains(cube) && !capitals.iter().any(|other| other != capital && other.disc(radius).contains(cube))
+            }).collect();
+            // Sorted, so that the same seed always changes the same tiles
+            cubes.sort();
+            cubes
+        };
+        // Every step moves one count towards the others
+        for _ in 0..self.len() {
+            let counts = self.localities_around(capitals, radius);
+            let (poorest, min) = counts.iter().enumerate().min_by_key(|(_, count)| **count).unwrap();
+            let (richest, max) = counts.iter().enumerate().max_by_key(|(_, count)| **count).unwrap();
+            if min == max {break}
+
+            let free: Vec<Cube<i32>> = reach_only(&capitals[poorest]).into_iter().filter(|cube| {
+                self.get(cube).is_some_and(|tile| tile.locality.is_none() && !matches!(tile.category, TileCategory::Water))
+            }).collect();
+            if !free.is_empty() {
+                let cube = free[rng::with_rng(|rng| rng.gen_range(0..free.len()))];
+                self.place_locality(&cube, LocalityCategory::City, locality_names);
+                continue
+            }
+            let taken: Vec<Cube<i32>> = reach_only(&capitals[richest]).into_iter().filter(|cube| {
+                self.get(cube).is_some_and(|tile| tile.locality.is_some())
+            }).collect();
+            if taken.is_empty() {break}
+            let cube = taken[rng::with_rng(|rng| rng.gen_range(0..taken.len()))];
+            // Removed and reinserted to keep the airport cache up to date
+            let mut tile = self.remove(&cube).unwrap();
+            tile.locality = None;
+            self.insert(cube, tile);
+        }
+    }
+
+    fn report_balance(&self, capitals: &[Cube<i32>], balance: &BalanceSettings) {
+        let counts = self.localities_around(capitals, balance.radius);
+        let distances = self.capital_distances(capitals);
+        let connection = match distances.iter().all(|distance| distance.is_some()) {
+            true => format!("at least {} steps apart over land", distances.iter().flatten().min().map_or(0, |d| *d)),
+            false => "not all connected by land".to_string(),
+        };
+        let is_balanced = counts.iter().all(|count| *count == counts[0]) && self.broken_capital_constraints(capitals, balance.min_distance) == 0;
+        println!(
+            "Capitals {}: {:?} localities within {} tiles, {}.",
+            if is_balanced {"balanced"} else {"unbalanced"}, counts, balance.radius, connection,
+        );
+    }
+    /// Gives every player one of the unowned capitals, in the order of their cubes.
+    fn gen_existing_capitals(&mut self, players: &mut Vec<Player>) {
+        let mut capitals: Vec<Cube<i32>> = self.iter().filter(|(_, t)| {
+            t.owner_index.is_none() && t.locality.as_ref().is_some_and(|l| matches!(l.category, LocalityCategory::Capital))
+        }).map(|(c, _)| *c).collect();
+        capitals.sort();
+        if capitals.len() < players.len() {
+            println!("Only {} capitals for {} players.", capitals.len(), players.len());
+        }
+        for (player_index, (player, cube)) in players.iter_mut().zip(capitals).enumerate() {
+            self.set_tile_owner(&cube, player_index);
             player.capital_pos = Some(cube);
-            let set = self.cubes_by_ownership.entry(player_index).or_insert(HashSet::new());
-            set.insert(cube);
-        });
+        }
     }
-    fn gen_maxdist_capitals(&mut self, locality_names: &mut Vec<&str>, mut players: &mut Vec<Player>) {
-        unimplemented!()
+
+    /// Number of steps from the start to every cube, moving through any tile of the map.
+    fn path_distances(&self, start: &Cube<i32>) -> HashMap<Cube<i32>, i32> {
+        let mut distances = HashMap::from([(*start, 0)]);
+        let mut frontier = std::collections::VecDeque::from([*start]);
+        while let Some(current) = frontier.pop_front() {
+            let distance = distances[&current];
+            for neighbour in current.disc(1) {
+                if self.contains_key(&neighbour) && !distances.contains_key(&neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    frontier.push_back(neighbour);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Number of steps from every cube to the nearest cube at the edge of the map.
+    fn edge_distances(&self) -> HashMap<Cube<i32>, i32> {
+        let mut distances = HashMap::new();
+        let mut frontier = std::collections::VecDeque::new();
+        for cube in self.keys() {
+            if cube.disc(1).iter().any(|neighbour| !self.contains_key(neighbour)) {
+                distances.insert(*cube, 0);
+                frontier.push_back(*cube);
+            }
+        }
+        while let Some(current) = frontier.pop_front() {
+            let distance = distances[&current];
+            for neighbour in current.disc(1) {
+                if self.contains_key(&neighbour) && !distances.contains_key(&neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    frontier.push_back(neighbour);
+                }
+            }
+        }
+        distances
+    }
+
+    /// Places the capitals on land so that the smallest path distance between any two of them is as large as possible.
+    /// Candidates are picked farthest point first, then every capital is moved while that increases its distance to the others.
+    fn gen_maxdist_capitals(&mut self, locality_names: &mut Vec<&str>, players: &mut Vec<Player>, edge_distance: i32) {
+        if players.is_empty() {return}
+        let edge_distances = self.edge_distances();
+        let is_land = |cube: &Cube<i32>| self.get(cube).is_some_and(|tile| !matches!(tile.category, TileCategory::Water));
+        let has_room = |cube: &Cube<i32>| {
+            let surroundings = cube.disc(CAPITAL_SURROUNDINGS_RADIUS);
+            let land = surroundings.iter().filter(|c| is_land(c)).count();
+            let localities = surroundings.iter().filter(|c| *c != cube && self.get(c).is_some_and(|tile| tile.locality.is_some())).count();
+            land * 3 >= surroundings.len() * 2 && localities >= MIN_LOCALITIES_AROUND_CAPITAL
+        };
+        let is_far_from_edge = |cube: &Cube<i32>| edge_distances.get(cube).is_some_and(|&distance| distance >= edge_distance);
+
+        // Sorted, so that the same seed always places the same capitals
+        let mut land: Vec<Cube<i32>> = self.keys().filter(|cube| is_land(cube)).copied().collect();
+        land.sort();
+        // Requirements are relaxed one by one when the map is too small or crowded to meet them
+        let mut candidates: Vec<Cube<i32>> = land.iter().filter(|cube| is_far_from_edge(cube) && has_room(cube)).copied().collect();
+        if candidates.len() < players.len() {
+            println!("Not enough room around capitals, placing them anywhere away from the edge.");
+            candidates = land.iter().filter(|cube| is_far_from_edge(cube)).copied().collect();
+        }
+        if candidates.len() < players.len() {
+            println!("Not enough land away from the edge, placing capitals anywhere.");
+            candidates = land;
+        }
+        if candidates.len() < players.len() {
+            println!("Not enough land for {} capitals.", players.len());
+            return
+        }
+
+        let min_distance = |cube: &Cube<i32>, distance_maps: &[&HashMap<Cube<i32>, i32>]| {
+            distance_maps.iter().map(|distances| *distances.get(cube).unwrap_or(&i32::MAX)).min().unwrap_or(i32::MAX)
+        };
+        // None once every candidate is a capital
+        let farthest = |capitals: &[Cube<i32>], distance_maps: &[&HashMap<Cube<i32>, i32>]| {
+            candidates.iter().filter(|cube| !capitals.contains(cube))
+                .map(|cube| (*cube, min_distance(cube, distance_maps)))
+                .max_by_key(|(_, distance)| *distance)
+        };
+
+        let first = candidates[rng::with_rng(|rng| rng.gen_range(0..candidates.len()))];
+        let mut capitals = vec![first];
+        let mut distance_maps = vec![self.path_distances(&first)];
+        while capitals.len() < players.len() {
+            let Some((next, _)) = farthest(&capitals, &distance_maps.iter().collect::<Vec<_>>()) else {break};
+            capitals.push(next);
+            distance_maps.push(self.path_distances(&next));
+        }
+        for _ in 0..MAXDIST_IMPROVEMENT_ROUNDS {
+            let mut is_improved = false;
+            for index in 0..capitals.len() {
+                let others: Vec<&HashMap<Cube<i32>, i32>> = distance_maps.iter().enumerate().filter(|(i, _)| *i != index).map(|(_, d)| d).collect();
+                let current = min_distance(&capitals[index], &others);
+                let Some((best, best_distance)) = farthest(&capitals, &others) else {continue};
+                if best_distance > current {
+                    capitals[index] = best;
+                    distance_maps[index] = self.path_distances(&best);
+                    is_improved = true;
+                }
+            }
+            if !is_improved {break}
+        }
+
+        for (player_index, (player, cube)) in players.iter_mut().zip(capitals).enumerate() {
+            // Removed and reinserted to keep the ownership and airport caches up to date
+            let mut tile = self.remove(&cube).unwrap();
+            let name = match &tile.locality {
+                Some(locality) => locality.name.clone(),
+                None => locality_names.pop().unwrap_or("capital").to_string(),
+            };
+            tile.locality = Some(Locality::new(&name, LocalityCategory::Capital));
+            tile.owner_index = Some(player_index);
+            self.insert(cube, tile);
+            player.capital_pos = Some(cube);
+        }
     }
 
     fn choose_capitals_gen(&mut self, gen: CapitalsGen, mut players: &mut Vec<Player>, locality_names: &mut Vec<&str>) {
         match gen {
             CapitalsGen::Classic => self.gen_classic_capitals(locality_names, &mut players),
-            CapitalsGen::Random => self.gen_random_capitals(locality_names, &mut players),
-            CapitalsGen::MaxDist => self.gen_maxdist_capitals(locality_names, &mut players),
+            CapitalsGen::Random(balance) => self.gen_random_capitals(locality_names, &mut players, &balance),
+            CapitalsGen::Existing => self.gen_existing_capitals(&mut players),
+            CapitalsGen::MaxDist{edge_distance} => self.gen_maxdist_capitals(locality_names, &mut players, edge_distance),
         }
     }
 
@@ -440,21 +786,74 @@ impl World {
         &mut self,
         players: &mut Vec<Player>,
         shape_gen: ShapeGen,
+        terrain_gen: TerrainGen,
         river_gen: RiverGen,
         localities_gen: LocalitiesGen,
         capitals_gen: CapitalsGen,
         locality_names: &mut Vec<&str>,
         init_layout: &crate::cubic::Layout<f32>,
     ) {
+        // Custom shapes are moved to the origin, and real cities along with them
+        let shape_origin = match &shape_gen {
+            ShapeGen::Custom(shape) => shape.iter().fold((f32::MAX, f32::MAX), |(x, y), p| (x.min(p.0), y.min(p.1))),
+            _ => (0., 0.),
+        };
         self.choose_shape_gen(shape_gen, init_layout);
-        // self.gen_water();
-        self.choose_river_gen(river_gen, init_layout);
-        self.choose_localities_gen(localities_gen, locality_names);
+        self.choose_terrain_gen(terrain_gen);
+        self.choose_river_gen(river_gen);
+        self.choose_localities_gen(localities_gen, locality_names, players.len(), shape_origin, init_layout);
         self.choose_capitals_gen(capitals_gen, players, locality_names);
     }
 }
 
 impl World {
+    /// Reseeds the generator, so that the same settings always generate the same world.
+    pub fn generate_with_settings(
+        &mut self,
+        settings: &WorldSettings,
+        players: &mut Vec<Player>,
+        locality_names: &mut Vec<&str>,
+        init_layout: &crate::cubic::Layout<f32>,
+    ) {
+        rng::seed(settings.seed);
+        let is_custom = matches!(settings.shape, ShapeKind::Custom) && !settings.custom_shape.is_empty();
+        if matches!(settings.shape, ShapeKind::Custom) && !is_custom {
+            println!("No custom shape loaded, generating a hexagonal world instead.");
+        }
+        let shape_gen = match settings.shape {
+            ShapeKind::Classic => ShapeGen::Classic(2 * settings.size + 4, settings.size + 3),
+            ShapeKind::Custom if is_custom => ShapeGen::Custom(settings.custom_shape.clone()),
+            ShapeKind::Hexagonal | ShapeKind::Custom => ShapeGen::Hexagonal(settings.size),
+        };
+        let terrain_gen = match settings.terrain {
+            Some(preset) => TerrainGen::Noise(preset, settings.water_ratio),
+            None => TerrainGen::Land,
+        };
+        let is_real = matches!(settings.locality_placement, LocalitiesKind::Real) && is_custom;
+        let localities_gen = match settings.locality_placement {
+            LocalitiesKind::Spaced => LocalitiesGen::RandomOts(settings.localities),
+            LocalitiesKind::Real if is_real => LocalitiesGen::Real(settings.real_cities.clone()),
+            LocalitiesKind::Random | LocalitiesKind::Real => LocalitiesGen::Random(settings.localities),
+        };
+        let capitals_gen = match settings.capitals {
+            // Real cities already place the capitals
+            _ if is_real => CapitalsGen::Existing,
+            CapitalsKind::Random => CapitalsGen::Random(settings.balance),
+            CapitalsKind::Classic => CapitalsGen::Classic,
+            CapitalsKind::MaxDist => CapitalsGen::MaxDist{edge_distance: MAXDIST_EDGE_DISTANCE},
+        };
+        self.generate(
+            players,
+            shape_gen,
+            terrain_gen,
+            RiverGen::Random(settings.rivers, RIVER_MEANDER),
+            localities_gen,
+            capitals_gen,
+            locality_names,
+            init_layout,
+        );
+    }
+
     pub fn draw_shape_outline(mut shape: Vec<(f32, f32)>, layout: &crate::cubic::Layout<f32>, init_layout: &crate::cubic::Layout<f32>) {
         shape.push(shape[0]);
         for j in 1..shape.len() {
@@ -583,7 +982,6 @@ impl World {
             }
         }
 
-        println!("map: {:?}", map);
         println!("map len: {}", map.len());
         map
         
@@ -644,7 +1042,44 @@ impl World {
 //     inside
 // }
 
-fn is_inside_polygon(polygon: &Vec<(f32, f32)>, point: Pixel<f32>) -> bool {
+/// Pseudo random value in [0, 1) for a lattice point.
+fn lattice_value(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4eb2d) ^ (y as u32).wrapping_mul(0x165667b1) ^ seed.wrapping_mul(0x9e3779b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Smoothly interpolated value noise in [0, 1).
+fn value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let top = lattice_value(ix, iy, seed) * (1. - tx) + lattice_value(ix + 1, iy, seed) * tx;
+    let bottom = lattice_value(ix, iy + 1, seed) * (1. - tx) + lattice_value(ix + 1, iy + 1, seed) * tx;
+    top * (1. - ty) + bottom * ty
+}
+
+/// Sum of NOISE_OCTAVES octaves of value noise, normalised to [0, 1).
+fn fractal_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let mut total = 0.;
+    let mut amplitude = 1.;
+    let mut frequency = 1.;
+    let mut max_total = 0.;
+    for octave in 0..NOISE_OCTAVES {
+        total += amplitude * value_noise(x * frequency, y * frequency, seed.wrapping_add(octave as u32));
+        max_total += amplitude;
+        amplitude /= 2.;
+        frequency *= 2.;
+    }
+    total / max_total
+}
+
+pub fn is_inside_polygon(polygon: &Vec<(f32, f32)>, point: Pixel<f32>) -> bool {
     let (px, py) = (point.0, point.1);
     let mut inside = false;
     let mut j = polygon.len() - 1;
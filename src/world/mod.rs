@@ -2,7 +2,8 @@ pub mod gen;
 
 use crate::Cube;
 use crate::DIRECTIONS;
-use crate::AI;
+use crate::agent::Bot;
+use crate::diplomacy::Diplomacy;
 
 use std::char::MAX;
 use std::collections::HashSet;
@@ -34,9 +35,14 @@ const MORALE_BONUS_ANNEX_SOVEREIGN_CAPITAL_ALL: i32 = 50;
 const MORALE_PENALTY_LOSING_CITY: i32 = 10;
 const MORALE_PENALTY_PER_MANPOWER_LOSING_BATTLE: f32 = 0.1;
 const MORALE_PENALTY_IDLE_ARMY: i32 = 1;
+const MORALE_PENALTY_OUT_OF_SUPPLY: i32 = 5;
+const MANPOWER_ATTRITION_OUT_OF_SUPPLY: i32 = 2;
 const BASE_GROWTH_CITY: i32 = 5;
 const BASE_GROWTH_CAPITAL: i32 = 10;
 const BONUS_GROWTH_PER_TILE: i32 = 1;
+const VISION_RANGE_TERRITORY: usize = 1;
+const VISION_RANGE_LOCALITY: usize = 2;
+const VISION_RANGE_ARMY: usize = 2;
 
 // My own made up constants
 const BASE_GROWTH_SATELLITE_CAPITAL: i32 = 7;
@@ -47,23 +53,34 @@ const MORALE_BONUS_ANNEX_SATELLITE_CAPITAL_ALL: i32 = 25;
 pub struct Player {
     pub name: String,
     pub actions: i32,
-    pub ai: Option<AI>,
+    pub ai: Option<Bot>,
     pub selection: Option<Cube<i32>>,
 
     // self.camera = None
     pub capital_pos: Option<Cube<i32>>,
+    // Defeated players keep their index, but are skipped in the turn order.
+    #[serde(default)]
+    pub is_defeated: bool,
+    // Last known state of every tile this player has seen, used by the fog of war.
+    #[serde(default)]
+    pub last_seen: HashMap<Cube<i32>, Tile>,
+    // Players sharing a team are permanently allied and win together.
+    #[serde(default)]
+    pub team: Option<usize>,
     // self.color = color
-    // self.is_defeated = False
 }
 
 impl Player {
-    pub fn new(name: &str, ai: Option<AI>) -> Self {
+    pub fn new(name: &str, ai: Option<Bot>) -> Self {
         Player {
             name: name.to_string(),
             actions: ACTIONS_PER_TURN,
             ai,
             selection: None,
             capital_pos: None,
+            is_defeated: false,
+            last_seen: HashMap::new(),
+            team: None,
         }
     }
     pub fn skip_turn(&mut self) {
@@ -105,6 +122,17 @@ impl From<LocalityCategory> for Locality {
     }
 }
 
+impl LocalityCategory {
+    /// Armies connected through own territory to one of these are in supply.
+    pub fn is_supply_source(&self) -> bool {
+        match self {
+            LocalityCategory::City | LocalityCategory::PortCity => true,
+            LocalityCategory::Capital | LocalityCategory::SatelliteCapital => true,
+            LocalityCategory::Airport => false,
+        }
+    }
+}
+
 impl Display for LocalityCategory {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match *self {
@@ -118,7 +146,7 @@ impl Display for LocalityCategory {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Locality {
     pub name: String,
     pub category: LocalityCategory, 
@@ -155,7 +183,7 @@ impl Display for TileCategory {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Tile {
     pub owner_index: Option<usize>,
     pub category: TileCategory,
@@ -186,6 +214,17 @@ impl Tile {
         }
     }
 
+    /// A copy of the tile as seen by a player who has never explored it:
+    /// terrain and localities are known, owners and armies are not.
+    pub fn unexplored(&self) -> Self {
+        Tile {
+            owner_index: None,
+            category: self.category.clone(),
+            locality: self.locality.clone(),
+            army: None,
+        }
+    }
+
     pub fn is_capturable(&self) -> bool {
         match self.category {
             TileCategory::Water => false,
@@ -239,7 +278,7 @@ impl Display for Tile {
 
 // Players interact with the game world by issuing commands to tiles containing an army,
 // effectively moving armies across tiles.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Army {
     pub manpower: i32,
     pub morale: i32,
@@ -276,6 +315,12 @@ impl Army {
         let minimum_morale = min(self.manpower, total_manpower / 50);
         self.morale = max(minimum_morale, self.morale - penalty);
     }
+    // Lose manpower to attrition, keeping morale within the new manpower.
+    fn apply_attrition(&mut self, manpower_loss: i32) {
+        assert!(manpower_loss > 0);
+        self.manpower = max(0, self.manpower - manpower_loss);
+        self.morale = min(self.manpower, self.morale);
+    }
     pub fn combat_strength(&self) -> i32 {
         self.manpower + self.morale
     }
@@ -290,6 +335,7 @@ impl Display for Army {
 // pub struct World(pub HashMap<Cube<i32>, Tile>);
 
 // #[derive(Serialize)]
+#[derive(Clone)]
 pub struct World {
     pub world: HashMap<Cube<i32>, Tile>,
     // #[serde(skip)]
@@ -298,6 +344,12 @@ pub struct World {
     pub cubes_with_airport: HashSet<Cube<i32>>,
     // #[serde(skip)]
     pub rivers: HashSet<crate::river::CubeSide>,
+    // Mirrors Game.diplomacy, so that movement rules can respect pacts.
+    pub diplomacy: Diplomacy,
+    // Worlds cloned by the AI to simulate orders.
+    pub is_simulation: bool,
+    // Mirrors Game.headless.
+    pub is_headless: bool,
 }
 
 impl Serialize for World {
@@ -332,12 +384,16 @@ impl<'de> Deserialize<'de> for World {
         });
 
         let rivers = HashSet::new();
+        let diplomacy = Diplomacy::new();
 
         Ok(World {
             world,
             cubes_by_ownership,
             cubes_with_airport,
             rivers,
+            diplomacy,
+            is_simulation: false,
+            is_headless: false,
         })
     }
 }
@@ -378,6 +434,9 @@ impl World {
             cubes_by_ownership: HashMap::new(),
             cubes_with_airport: HashSet::new(),
             rivers: HashSet::new(),
+            diplomacy: Diplomacy::new(),
+            is_simulation: false,
+            is_headless: false,
         }
     }
     pub fn insert(&mut self, key: Cube<i32>, value: Tile) {
@@ -524,15 +583,23 @@ impl World {
             Some(tile) => tile.army.is_none() 
                               && tile.locality.is_none()
                               && !matches!(tile.category, TileCategory::Water)
-                              && !matches!(origin_tile.category, TileCategory::Water),
+                              && !matches!(origin_tile.category, TileCategory::Water)
+                              && (tile.owner_index == origin_tile.owner_index
+                                  || self.diplomacy.can_attack_owner(origin_tile.owner_index, tile.owner_index)),
             None => false,
         }
     }
 
     pub fn is_cube_targetable(&self, origin: &Cube<i32>, target: &Cube<i32>) -> bool {
         let origin_t = self.get(origin).unwrap();
+        let origin_owner = origin_t.army.as_ref().and_then(|army| army.owner_index);
         match self.get(target) {
             Some(tile) => {
+                // Armies cannot attack or capture the tiles of players they are at peace with
+                let target_owner = tile.army.as_ref().map_or(tile.owner_index, |army| army.owner_index);
+                if target_owner != origin_owner && !self.diplomacy.can_attack_owner(origin_owner, target_owner) {
+                    return false
+                }
                 match tile.category {
                     TileCategory::Water => {
                         matches!(origin_t.category, TileCategory::Water)
@@ -560,15 +627,19 @@ impl World {
                     },
                     _ => true,
                 };
+                let origin_owner = origin_t.army.as_ref().unwrap().owner_index;
+                let is_friendly = |owner: Option<usize>| {
+                    owner == origin_owner || owner.zip(origin_owner).is_some_and(|(a, b)| self.diplomacy.are_allied(a, b))
+                };
                 let arm_cond = match &tile.army {
                     Some(army) => {
-                        army.owner_index == origin_t.army.as_ref().unwrap().owner_index
+                        is_friendly(army.owner_index)
                     },
                     None => true,
                 };
                 let loc_cond = match &tile.locality {
                     Some(_) => {
-                        tile.owner_index == origin_t.owner_index
+                        tile.owner_index == origin_t.owner_index || is_friendly(tile.owner_index)
                     },
                     None => true,
                 };
@@ -609,18 +680,6 @@ impl World {
         visited
     }
 
-    pub fn is_there_capturable_tile_within_range(&self, cube: &Cube<i32>) -> bool {
-        let valid_targets = self.get_reachable_cubes(&cube);
-        for target in valid_targets {
-            let origin = self.get(cube).unwrap();
-            let target = self.get(&target).unwrap();
-            if origin.owner_index != target.owner_index {
-                return true
-            }
-        }
-        false
-    }
-
     pub fn train_armies(&mut self, &player_index: &usize) {
         let (world, cubes_by_ownership) = self.split_fields();
         if !cubes_by_ownership.get(&player_index).is_some() {return} // TODO: make this redundant in the future
@@ -692,6 +751,114 @@ impl World {
         }
     }
 
+    /// Flood fills through the player's own territory, starting from every owned city or capital.
+    /// Returns the set of owned cubes which are connected to a supply source.
+    pub fn get_supplied_cubes(&self, player_index: usize) -> HashSet<Cube<i32>> {
+        let empty_set = HashSet::new();
+        let player_cubes = self.cubes_by_ownership.get(&player_index).unwrap_or(&empty_set);
+        let mut frontier: VecDeque<Cube<i32>> = player_cubes.iter().filter(|cube| {
+            self.world.get(cube).is_some_and(|tile| tile.locality.as_ref().is_some_and(|l| l.category.is_supply_source()))
+        }).copied().collect();
+        let mut reached: HashSet<Cube<i32>> = frontier.iter().copied().collect();
+
+        while let Some(current) = frontier.pop_front() {
+            for direction in DIRECTIONS {
+                let next = current + direction;
+                if player_cubes.contains(&next) && !reached.contains(&next) {
+                    frontier.push_back(next);
+                    reached.insert(next);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Returns the owned cubes which are cut off from every supply source of the player.
+    pub fn get_encircled_cubes(&self, player_index: usize) -> HashSet<Cube<i32>> {
+        let empty_set = HashSet::new();
+        let player_cubes = self.cubes_by_ownership.get(&player_index).unwrap_or(&empty_set);
+        let supplied_cubes = self.get_supplied_cubes(player_index);
+        player_cubes.difference(&supplied_cubes).copied().collect()
+    }
+
+    // Called from within Game at the start of the turn. Armies cut off from supply lose morale and manpower.
+    pub fn apply_supply_attrition(&mut self, player_index: usize) {
+        let total_manpower = player_total_manpower(&self, player_index);
+        let encircled_cubes = self.get_encircled_cubes(player_index);
+        let is_logged = self.is_logged();
+
+        for cube in encircled_cubes {
+            let tile = self.world.get_mut(&cube).unwrap();
+            if let Some(army) = &mut tile.army {
+                army.apply_morale_penalty(MORALE_PENALTY_OUT_OF_SUPPLY, total_manpower);
+                army.apply_attrition(MANPOWER_ATTRITION_OUT_OF_SUPPLY);
+                if is_logged {
+                    println!("Player {} army at {} is out of supply: {}", player_index, cube, army);
+                }
+                if army.manpower == 0 {
+                    tile.army = None;
+                }
+            }
+        }
+    }
+
+    /// Returns the cubes a player can currently see: own territory and its surroundings,
+    /// plus a radius around every owned locality and army.
+    pub fn get_visible_cubes(&self, player_index: usize) -> HashSet<Cube<i32>> {
+        let mut visible = HashSet::new();
+        if let Some(player_cubes) = self.cubes_by_ownership.get(&player_index) {
+            for cube in player_cubes {
+                let range = match self.world[cube].locality {
+                    Some(_) => VISION_RANGE_LOCALITY,
+                    None => VISION_RANGE_TERRITORY,
+                };
+                visible.extend(cube.disc(range));
+            }
+        }
+        for (cube, tile) in self.world.iter() {
+            if tile.army.as_ref().is_some_and(|army| army.owner_index == Some(player_index)) {
+                visible.extend(cube.disc(VISION_RANGE_ARMY));
+            }
+        }
+        visible.retain(|cube| self.world.contains_key(cube));
+        visible
+    }
+
+    /// Stores the current state of every tile visible to the player in its memory.
+    pub fn record_last_seen(&self, player_index: usize, last_seen: &mut HashMap<Cube<i32>, Tile>) {
+        for cube in self.get_visible_cubes(player_index) {
+            last_seen.insert(cube, self.world[&cube].clone());
+        }
+    }
+
+    /// Builds the world as a player knows it. Visible tiles are shown as they are,
+    /// the rest show their last known state, or only their terrain if never seen.
+    /// Simulations and headless games log nothing, so that tournaments print one line per game.
+    pub fn is_logged(&self) -> bool {
+        !self.is_simulation && !self.is_headless
+    }
+
+    pub fn get_player_view(&self, player_index: usize, last_seen: &HashMap<Cube<i32>, Tile>) -> World {
+        let visible_cubes = self.get_visible_cubes(player_index);
+        let mut view = World::new();
+        view.rivers = self.rivers.clone();
+        view.diplomacy = self.diplomacy.clone();
+        view.is_simulation = self.is_simulation;
+        view.is_headless = self.is_headless;
+        for (cube, tile) in self.world.iter() {
+            let known_tile = if visible_cubes.contains(cube) {
+                tile.clone()
+            } else {
+                match last_seen.get(cube) {
+                    Some(seen_tile) => seen_tile.clone(),
+                    None => tile.unexplored(),
+                }
+            };
+            view.insert(*cube, known_tile);
+        }
+        view
+    }
+
     pub fn can_player_issue_a_command(&self, player_index: &usize) -> bool {
         self.cubes_by_ownership
             .get(player_index)
@@ -705,11 +872,30 @@ impl World {
     }
     // Transfer the ownership of all of defeated_player tiles to player.
     pub fn surrender_to_player(&mut self, surrendering_player_index: &usize, conquering_player_index: &usize) {
+        self.disband_armies(surrendering_player_index);
+        let cubes: Vec<Cube<i32>> = self.cubes_by_ownership.get(surrendering_player_index)
+            .map_or(vec!(), |cubes| cubes.iter().copied().collect());
+        for cube in cubes {
+            self.set_tile_owner(&cube, *conquering_player_index);
+        }
+    }
+
+    // Leave all of the resigning player tiles without an owner.
+    pub fn abandon_player(&mut self, resigning_player_index: &usize) {
+        self.disband_armies(resigning_player_index);
         let (world, cubes_by_ownership) = self.split_fields();
-        for cube in cubes_by_ownership.get(&surrendering_player_index).unwrap() {
-            let tile = world.get_mut(cube).unwrap();
-            tile.army = None;
-            tile.owner_index = Some(*conquering_player_index);
+        let cubes = cubes_by_ownership.get_mut(resigning_player_index).map_or(HashSet::new(), std::mem::take);
+        for cube in cubes {
+            world.get_mut(&cube).unwrap().owner_index = None;
+        }
+    }
+
+    // Remove every army of the player, including those at sea.
+    fn disband_armies(&mut self, player_index: &usize) {
+        for tile in self.world.values_mut() {
+            if tile.army.as_ref().is_some_and(|army| army.owner_index == Some(*player_index)) {
+                tile.army = None;
+            }
         }
     }
     
@@ -776,7 +962,9 @@ fn attack(mut world: &mut World, origin_cube: &Cube<i32>, target_cube: &Cube<i32
     let mut origin = world.remove(origin_cube).unwrap();
     let mut target = world.remove(target_cube).unwrap();
 
-    println!("{:?} attacks {:?} with {} against {}", origin.owner_index, target.owner_index, origin.army.as_ref().unwrap(), target.army.as_ref().unwrap());
+    if world.is_logged() {
+        println!("{:?} attacks {:?} with {} against {}", origin.owner_index, target.owner_index, origin.army.as_ref().unwrap(), target.army.as_ref().unwrap());
+    }
 
     origin.army.as_mut().unwrap().can_move = false;
 
@@ -839,7 +1027,9 @@ fn apply_morale_penalty_losing_combat(mut world: &mut World, losing_player_index
     let penalty = (MORALE_PENALTY_PER_MANPOWER_LOSING_BATTLE * manpower_lost as f32) as i32; // implicit floor
     if penalty == 0 { return }
     let total_manpower = player_total_manpower(&world, losing_player_index);
-    println!("Player {:?} suffers {} morale penalty", losing_player_index, penalty);
+    if world.is_logged() {
+        println!("Player {:?} suffers {} morale penalty", losing_player_index, penalty);
+    }
     for tile in world.values_mut() {
         if tile.owner_index == Some(losing_player_index) {
             if let Some(army) = tile.army.as_mut() {
@@ -868,7 +1058,9 @@ fn capture_tile(world: &mut World, origin_cube: &Cube<i32>, target_cube: &Cube<i
         Some(index) => format!(" from Player {}", index),
         None => "".to_string(),
     };
-    println!("{} captures {} {}{}", origin_owner, target, target_cube, from_clause);
+    if world.is_logged() {
+        println!("{} captures {} {}{}", origin_owner, target, target_cube, from_clause);
+    }
 
     let mut capturing_army_morale_bonus = 0;
     let mut origin_owner_morale_bonus = 0;